@@ -0,0 +1,75 @@
+// paints into the field along the segment start -> end (set from Rust)
+struct BrushParams {
+    start: vec2<f32>, // grid position of the last cursor position
+    end: vec2<f32>, // grid position of the cursor now
+    radius: f32, // in grid pixels
+    strength: f32, // 0..1, how far a pixel moves towards the target per stroke
+    falloff: f32, // 0 = hard edge, 1 = fades out from the center
+    mode: u32, // 0 = paint V, 1 = paint U, 2 = erase, 3 = wall, 4 = clear wall
+    origin: vec2<u32>, // corner of the dispatched rectangle
+    size: vec2<u32>, // size of the dispatched rectangle
+};
+
+@group(0) @binding(0)
+var<uniform> u_brush : BrushParams;
+
+@group(0) @binding(1)
+var src_texture : texture_2d<f32>; // copy of the current source
+
+@group(0) @binding(2)
+var dst_texture : texture_storage_2d<rgba32float, write>; // the current source
+
+@group(0) @binding(3)
+var mask_texture : texture_storage_2d<r32float, write>; // 1 = wall
+
+#include "common.wgsl"
+
+// distance from p to the segment a -> b
+fn distance_to_segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let ab = b - a;
+    let len_sq = dot(ab, ab);
+    var t = 0.0;
+    if (len_sq > 0.0) {
+        t = clamp(dot(p - a, ab) / len_sq, 0.0, 1.0);
+    }
+    return length(p - (a + ab * t));
+}
+
+// WG_X and WG_Y are #defines from Rust
+@compute @workgroup_size(WG_X, WG_Y)
+fn main(@builtin(global_invocation_id) gid : vec3<u32>) {
+    if (gid.x >= u_brush.size.x || gid.y >= u_brush.size.y) { return; }
+
+    let x_y = vec2<i32>(u_brush.origin + gid.xy);
+    let center = vec2<f32>(x_y) + vec2<f32>(0.5, 0.5);
+    let dist = distance_to_segment(center, u_brush.start, u_brush.end);
+
+    // walls are either there or not, the whole footprint of the brush whatever the
+    // strength and falloff. the field is left alone
+    if (u_brush.mode >= 3u) {
+        if (dist <= u_brush.radius) {
+            let wall = select(0.0, 1.0, u_brush.mode == 3u);
+            textureStore(mask_texture, x_y, vec4<f32>(wall, 0.0, 0.0, 0.0));
+        }
+        return;
+    }
+
+    // full strength inside the hard core, smooth towards the edge
+    // (at least half a pixel wide so a hard brush is still anti aliased)
+    let core = u_brush.radius * (1.0 - u_brush.falloff);
+    let t = clamp((u_brush.radius - dist) / max(u_brush.radius - core, 0.5), 0.0, 1.0);
+    let weight = u_brush.strength * t * t * (3.0 - 2.0 * t);
+    if (weight <= 0.0) { return; }
+
+    var u_v = read_u_v(src_texture, x_y);
+    if (u_brush.mode == 0u) {
+        u_v.y = mix(u_v.y, 1.0, weight);
+    } else if (u_brush.mode == 1u) {
+        u_v.x = mix(u_v.x, 1.0, weight);
+    } else {
+        // back to U = 1, V = 0
+        u_v = mix(u_v, vec2<f32>(1.0, 0.0), weight);
+    }
+
+    textureStore(dst_texture, x_y, vec4<f32>(u_v, 0.0, 1.0));
+}
@@ -0,0 +1,105 @@
+// helpers shared by the shaders, pulled in with #include "common.wgsl"
+
+// sample a pixel from the input
+fn read_u_v(texture: texture_2d<f32>, x_y: vec2<i32>) -> vec2<f32> {
+    let dims = textureDimensions(texture);
+    
+    // bounds
+    let x = clamp(x_y.x, 0, i32(dims.x) - 1);
+    let y = clamp(x_y.y, 0, i32(dims.y) - 1);
+    
+    // read the pixel
+    let c = textureLoad(texture, vec2<i32>(x, y), 0);
+    return c.rg; // U V values
+}
+
+// boundary conditions (Boundary in rd_system.rs)
+const BOUNDARY_NEUMANN: u32 = 0u; // zero flux, the edge pixel continues outside
+const BOUNDARY_PERIODIC: u32 = 1u; // wraps around, the field tiles seamlessly
+const BOUNDARY_DIRICHLET: u32 = 2u; // fixed U and V outside the grid
+
+// x_y moved into the grid like a torus, a real modulo for any grid size
+// neighbors are at most 2 pixels outside (fourth order), which can be more than a whole
+// grid that is 1 pixel wide. shifting by 2 grids first keeps the % unsigned (signed %
+// keeps the sign of x_y, and llvmpipe even gave 0 for -1 % 3)
+fn wrap(x_y: vec2<i32>, dims: vec2<i32>) -> vec2<i32> {
+    return vec2<i32>(vec2<u32>(x_y + 2 * dims) % vec2<u32>(dims));
+}
+
+// what the field is at x_y, also outside the grid
+fn read_bounded(texture: texture_2d<f32>, x_y: vec2<i32>, boundary: u32, fixed_u_v: vec2<f32>) -> vec2<f32> {
+    let dims = vec2<i32>(textureDimensions(texture));
+    if (boundary == BOUNDARY_PERIODIC) {
+        return textureLoad(texture, wrap(x_y, dims), 0).rg;
+    }
+    let outside = any(x_y < vec2<i32>(0)) || any(x_y >= dims);
+    if (boundary == BOUNDARY_DIRICHLET && outside) {
+        return fixed_u_v;
+    }
+    // Neumann, clamping repeats the edge pixel
+    return read_u_v(texture, x_y);
+}
+
+// mask texture, 1 = wall. outside the grid is never a wall, the boundary handles that
+fn is_wall(mask: texture_2d<f32>, x_y: vec2<i32>, boundary: u32) -> bool {
+    let dims = vec2<i32>(textureDimensions(mask));
+    var p = x_y;
+    if (boundary == BOUNDARY_PERIODIC) {
+        p = wrap(x_y, dims);
+    }
+    if (any(p < vec2<i32>(0)) || any(p >= dims)) {
+        return false;
+    }
+    return textureLoad(mask, p, 0).r > 0.5;
+}
+
+// a neighbor of center, a wall acts like the boundary of the grid
+// (no flux: it looks like the center, dirichlet: the fixed values)
+fn read_neighbor(texture: texture_2d<f32>, mask: texture_2d<f32>, x_y: vec2<i32>, center: vec2<f32>, boundary: u32, fixed_u_v: vec2<f32>) -> vec2<f32> {
+    if (is_wall(mask, x_y, boundary)) {
+        return select(center, fixed_u_v, boundary == BOUNDARY_DIRICHLET);
+    }
+    return read_bounded(texture, x_y, boundary, fixed_u_v);
+}
+
+// weights of the laplacian, #defines from Rust (Stencil in rd_system.rs)
+// a weight that is not defined is 0 and its pixels are not read
+#ifndef STENCIL_CENTER
+#define STENCIL_CENTER -4.0
+#define STENCIL_EDGE 1.0
+#endif
+
+// the laplacian without the center term, what the IMEX Jacobi iteration needs on its own
+fn neighbor_sum(texture: texture_2d<f32>, mask: texture_2d<f32>, x_y: vec2<i32>, center: vec2<f32>, boundary: u32, fixed_u_v: vec2<f32>) -> vec2<f32> {
+    var sum = vec2<f32>(0.0);
+
+    // up, down, left, right
+    var edges = array<vec2<i32>, 4>(vec2<i32>(0, -1), vec2<i32>(0, 1), vec2<i32>(-1, 0), vec2<i32>(1, 0));
+    for (var i = 0; i < 4; i++) {
+        let near = read_neighbor(texture, mask, x_y + edges[i], center, boundary, fixed_u_v);
+        sum += STENCIL_EDGE * near;
+#ifdef STENCIL_FAR
+        // two away, but not through a wall (behind it is the wall again)
+        var far = near;
+        if (!is_wall(mask, x_y + edges[i], boundary)) {
+            far = read_neighbor(texture, mask, x_y + 2 * edges[i], center, boundary, fixed_u_v);
+        }
+        sum += STENCIL_FAR * far;
+#endif
+    }
+
+#ifdef STENCIL_CORNER
+    var corners = array<vec2<i32>, 4>(vec2<i32>(-1, -1), vec2<i32>(1, -1), vec2<i32>(-1, 1), vec2<i32>(1, 1));
+    for (var i = 0; i < 4; i++) {
+        sum += STENCIL_CORNER * read_neighbor(texture, mask, x_y + corners[i], center, boundary, fixed_u_v);
+    }
+#endif
+
+    return sum;
+}
+
+// laplacian with the stencil weights
+fn laplacian(texture: texture_2d<f32>, mask: texture_2d<f32>, x_y: vec2<i32>, boundary: u32, fixed_u_v: vec2<f32>) -> vec2<f32> {
+    let center = read_u_v(texture, x_y);
+    return STENCIL_CENTER * center + neighbor_sum(texture, mask, x_y, center, boundary, fixed_u_v);
+}
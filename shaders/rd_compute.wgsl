@@ -1,15 +1,17 @@
-// defining constants for Reaction Diffusion System
-const DU : f32 = 0.19; // diffusion rate for substance U
-const DV : f32 = 0.08; // diffusion rate for substance V
-const FEED : f32 = 0.0345; // Feed rate of U
-const KILL : f32 = 0.062; // V's killing rate
-
-struct TimeUniform {
+// parameters of the Reaction Diffusion System (set from Rust)
+struct SimParams {
+    du: f32, // diffusion rate for substance U
+    dv: f32, // diffusion rate for substance V
+    feed: f32, // Feed rate of U
+    kill: f32, // V's killing rate
     dt: f32,
+    use_param_map: u32, // 1 = feed/kill/du/dv come from param_texture
+    boundary: u32, // BOUNDARY_* in common.wgsl
+    boundary_u_v: vec2<f32>, // U and V outside the grid for BOUNDARY_DIRICHLET
 };
 
 @group(0) @binding(0)
-var<uniform> u_time : TimeUniform;
+var<uniform> u_params : SimParams;
 
 @group(0) @binding(1)
 var src_texture : texture_2d<f32>; // read from this
@@ -17,54 +19,183 @@ var src_texture : texture_2d<f32>; // read from this
 @group(0) @binding(2)
 var dst_texture : texture_storage_2d<rgba32float, write>;  // write to this
 
-// sample a pixel from the input
-fn read_u_v(texture: texture_2d<f32>, x_y: vec2<i32>) -> vec2<f32> {
-    let dims = textureDimensions(texture);
-    
-    // bounds
-    let x = clamp(x_y.x, 0, i32(dims.x) - 1);
-    let y = clamp(x_y.y, 0, i32(dims.y) - 1);
-    
-    // read the pixel
-    let c = textureLoad(texture, vec2<i32>(x, y), 0);
-    return c.rg; // U V values
+@group(0) @binding(3)
+var param_texture : texture_2d<f32>; // per pixel feed, kill, du, dv
+
+@group(0) @binding(4)
+var mask_texture : texture_2d<f32>; // 1 = wall
+
+@group(0) @binding(5)
+var stage_texture : texture_2d<f32>; // what the stage before wrote (Integrator in rd_system.rs)
+
+// pixels that stayed outside [0, 1] before the clamp, summed up over the grid (DivergenceFlags in Rust)
+struct Divergence {
+    pixels: atomic<u32>,
+    first: atomic<u32>, // smallest y * width + x
+};
+
+@group(0) @binding(6)
+var<storage, read_write> divergence : Divergence;
+
+#include "common.wgsl"
+
+// workgroup size, set from Rust when the pipeline is built
+override WG_X: u32 = 16u;
+override WG_Y: u32 = 16u;
+
+// the stage of a step this pipeline runs, also set from Rust (the defaults are Euler)
+override FIRST_STAGE: bool = true; // evaluates the source, later stages the stage texture
+override LAST_STAGE: bool = true; // writes the field, earlier stages the other stage texture
+override STAGE_WEIGHT: f32 = 1.0; // Runge-Kutta weight of this stage's rate
+override NEXT_OFFSET: f32 = 0.0; // the next stage evaluates y + NEXT_OFFSET * dt * rate
+override DETECT_DIVERGENCE: bool = false; // flag pixels the clamp would hide
+
+// how far outside [0, 1] a value can get before it counts
+// (a stable step overshoots a little at steep edges)
+const DIVERGENCE_MARGIN: f32 = 0.1;
+// steps in a row a pixel has to be outside before it counts as diverged. U = V = 1
+// (spots, the V brush) overshoots until the reaction used up U, about a dozen steps
+// with Heun at its limit. a blow-up stays outside. the streak is kept in the unused
+// B channel of the field
+const DIVERGENCE_STREAK: f32 = 30.0;
+
+// feed, kill, du, dv from the global parameters or the parameter map
+// du or dv below 0 in the map means the global one
+fn local_params(x_y: vec2<i32>) -> vec4<f32> {
+    if (u_params.use_param_map == 1u) {
+        let local = textureLoad(param_texture, x_y, 0);
+        let global = vec2<f32>(u_params.du, u_params.dv);
+        return vec4<f32>(local.xy, select(local.zw, global, local.zw < vec2<f32>(0.0)));
+    }
+    return vec4<f32>(u_params.feed, u_params.kill, u_params.du, u_params.dv);
+}
+
+// numerical calculation of the differential equation, how fast U and V change
+fn rate(u_v: vec2<f32>, lap_u_v: vec2<f32>, p: vec4<f32>) -> vec2<f32> {
+    let u = u_v.x;
+    let v = u_v.y;
+    let feed = p.x;
+    let kill = p.y;
+    let du = p.z * lap_u_v.x - u * v * v + feed * (1.0 - u);
+    let dv = p.w * lap_u_v.y + u * v * v - (feed + kill) * v;
+    return vec2<f32>(du, dv);
 }
 
-// laplacian 4 neighbor
-fn laplacian(texture: texture_2d<f32>, x_y: vec2<i32>) -> vec2<f32> {
-    let center = read_u_v(texture, x_y);
-    let up = read_u_v(texture, x_y + vec2<i32>(0, -1));
-    let down = read_u_v(texture, x_y + vec2<i32>(0, 1));
-    let left = read_u_v(texture, x_y + vec2<i32>(-1, 0));
-    let right = read_u_v(texture, x_y + vec2<i32>(1, 0));
+// the new field before the clamp, NaN fails both comparisons
+// returns the streak to store with it
+fn check_divergence(x_y: vec2<i32>, u_v: vec2<f32>) -> f32 {
+    if (!DETECT_DIVERGENCE) { return 0.0; }
+    let inside = all(u_v >= vec2<f32>(-DIVERGENCE_MARGIN)) && all(u_v <= vec2<f32>(1.0 + DIVERGENCE_MARGIN));
+    if (inside) { return 0.0; }
+
+    let streak = textureLoad(src_texture, x_y, 0).b + 1.0;
+    if (streak >= DIVERGENCE_STREAK) {
+        let width = textureDimensions(dst_texture).x;
+        atomicAdd(&divergence.pixels, 1u);
+        atomicMin(&divergence.first, u32(x_y.y) * width + u32(x_y.x));
+    }
+    return streak;
+}
 
-    let laplace = (up + down + left + right) - 4.0 * center;
-    return laplace;
+// what a wall stores, U = 1 and V = 0 in the field and the stage textures alike
+fn store_wall(x_y: vec2<i32>) {
+    if (LAST_STAGE) {
+        textureStore(dst_texture, x_y, vec4<f32>(1.0, 0.0, 0.0, 1.0));
+    } else {
+        textureStore(dst_texture, x_y, vec4<f32>(1.0, 0.0, 1.0, 0.0));
+    }
 }
 
-@compute @workgroup_size(16, 16)
+// one Runge-Kutta stage, Euler is the only stage of its step
+// the stage texture has the state to evaluate in RG and the weighted rates so far in BA
+@compute @workgroup_size(WG_X, WG_Y)
 fn main(@builtin(global_invocation_id) gid : vec3<u32>) { 
     let dims = textureDimensions(dst_texture);
-    let dt = u_time.dt;
+    let dt = u_params.dt;
 
     // bounds
     if (gid.x >= dims.x || gid.y >= dims.y) { return; }
 
     // pixel values and position and the calculating the diffusion
     let x_y = vec2<i32>(i32(gid.x), i32(gid.y));
-    let u_v = read_u_v(src_texture, x_y);
-    let lap_u_v = laplacian(src_texture, x_y);
 
-    var u = u_v.x;
-    var v = u_v.y;
+    // nothing grows inside a wall
+    if (is_wall(mask_texture, x_y, u_params.boundary)) {
+        store_wall(x_y);
+        return;
+    }
+
+    // the field at the start of the step
+    let y = read_u_v(src_texture, x_y);
+
+    var state = y;
+    var rates = vec2<f32>(0.0);
+    var lap_u_v: vec2<f32>;
+    if (FIRST_STAGE) {
+        lap_u_v = laplacian(src_texture, mask_texture, x_y, u_params.boundary, u_params.boundary_u_v);
+    } else {
+        let stage = textureLoad(stage_texture, x_y, 0);
+        state = stage.rg;
+        rates = stage.ba;
+        lap_u_v = laplacian(stage_texture, mask_texture, x_y, u_params.boundary, u_params.boundary_u_v);
+    }
+
+    let k = rate(state, lap_u_v, local_params(x_y));
+    rates += STAGE_WEIGHT * k;
+
+    if (LAST_STAGE) {
+        // then calculate the integral over time
+        let next = y + rates * dt;
+        let streak = check_divergence(x_y, next);
+        let u_v = clamp(next, vec2<f32>(0.0), vec2<f32>(1.0));
+        textureStore(dst_texture, x_y, vec4<f32>(u_v, streak, 1.0));
+    } else {
+        textureStore(dst_texture, x_y, vec4<f32>(y + NEXT_OFFSET * dt * k, rates));
+    }
+}
+
+// IMEX: the reaction explicit and the diffusion implicit
+//   (1 - dt D L) y' = y + dt R(y)
+// the right hand side goes into BA and is the first guess for y' in RG
+@compute @workgroup_size(WG_X, WG_Y)
+fn imex_reaction(@builtin(global_invocation_id) gid : vec3<u32>) {
+    let dims = textureDimensions(dst_texture);
+    if (gid.x >= dims.x || gid.y >= dims.y) { return; }
+    let x_y = vec2<i32>(i32(gid.x), i32(gid.y));
+
+    if (is_wall(mask_texture, x_y, u_params.boundary)) {
+        store_wall(x_y);
+        return;
+    }
+
+    let y = read_u_v(src_texture, x_y);
+    let rhs = y + u_params.dt * rate(y, vec2<f32>(0.0), local_params(x_y));
+    textureStore(dst_texture, x_y, vec4<f32>(rhs, rhs));
+}
+
+// one Jacobi iteration of the IMEX system, solved for the center pixel
+//   y'_c = (rhs + dt D neighbor_sum(y')) / (1 - dt D STENCIL_CENTER)
+@compute @workgroup_size(WG_X, WG_Y)
+fn imex_jacobi(@builtin(global_invocation_id) gid : vec3<u32>) {
+    let dims = textureDimensions(dst_texture);
+    if (gid.x >= dims.x || gid.y >= dims.y) { return; }
+    let x_y = vec2<i32>(i32(gid.x), i32(gid.y));
+
+    if (is_wall(mask_texture, x_y, u_params.boundary)) {
+        store_wall(x_y);
+        return;
+    }
 
-    // numerical calculation of the differential equation 
-    // then calculate the integral over time
-    let du = DU * lap_u_v.x - u * v * v + FEED * (1.0 - u);
-    let dv = DV * lap_u_v.y + u * v * v - (FEED + KILL) * v;
-    u = clamp(u + du * dt, 0.0, 1.0);
-    v = clamp(v + dv * dt, 0.0, 1.0);
+    let stage = textureLoad(stage_texture, x_y, 0);
+    let dt_d = u_params.dt * local_params(x_y).zw;
+    let sum = neighbor_sum(stage_texture, mask_texture, x_y, stage.rg, u_params.boundary, u_params.boundary_u_v);
+    let next = (stage.ba + dt_d * sum) / (1.0 - dt_d * STENCIL_CENTER);
 
-    let u_v_res= vec4<f32>(u, v, 0.0, 1.0);
-    textureStore(dst_texture, x_y, u_v_res);
+    if (LAST_STAGE) {
+        let streak = check_divergence(x_y, next);
+        let u_v = clamp(next, vec2<f32>(0.0), vec2<f32>(1.0));
+        textureStore(dst_texture, x_y, vec4<f32>(u_v, streak, 1.0));
+    } else {
+        textureStore(dst_texture, x_y, vec4<f32>(next, stage.ba));
+    }
 }
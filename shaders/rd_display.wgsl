@@ -4,6 +4,20 @@ var rd_texture : texture_2d<f32>;
 @group(0) @binding(1)
 var rd_sampler : sampler;
 
+// display settings (set from Rust)
+struct DisplayParams {
+    palette: u32, // 0 = cosine, 1 = grayscale, 2 = fire, 3 = ocean
+};
+
+@group(0) @binding(2)
+var<uniform> u_display : DisplayParams;
+
+@group(0) @binding(3)
+var mask_texture : texture_2d<f32>; // 1 = wall
+
+// walls are drawn in the same gray with every palette
+const WALL_COLOR = vec3<f32>(0.35, 0.35, 0.38);
+
 struct VSOut {
     @builtin(position) pos : vec4<f32>,
     // screen space not UV elements reaction
@@ -41,13 +55,45 @@ fn color_pallette(u: f32, v: f32) -> vec3<f32> {
     return d + c * cos(2.0 * (a * cl + b));
 }
 
+// black -> red -> yellow -> white where V is high
+fn fire_pallette(cl: f32) -> vec3<f32> {
+    let t = 1.0 - cl;
+    return clamp(vec3<f32>(3.0 * t, 3.0 * t - 1.0, 3.0 * t - 2.0), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// deep blue -> cyan -> white where V is high
+fn ocean_pallette(cl: f32) -> vec3<f32> {
+    let t = 1.0 - cl;
+    let deep = vec3<f32>(0.0, 0.05, 0.2);
+    let cyan = vec3<f32>(0.0, 0.6, 0.8);
+    if (t < 0.5) {
+        return mix(deep, cyan, t * 2.0);
+    }
+    return mix(cyan, vec3<f32>(1.0), t * 2.0 - 1.0);
+}
+
 @fragment
 fn fs_main(in : VSOut) -> @location(0) vec4<f32> {
     let u_v = textureSampleLevel(rd_texture, rd_sampler, in.uv, 0.0).rg; // only two channels 
     let u = u_v.x;
     let v = u_v.y;
 
-    let color_theme = color_pallette(u, v);
+    let cl = clamp(u - v, 0.0, 1.0);
+    var color_theme = color_pallette(u, v);
+    if (u_display.palette == 1u) {
+        color_theme = vec3<f32>(cl);
+    } else if (u_display.palette == 2u) {
+        color_theme = fire_pallette(cl);
+    } else if (u_display.palette == 3u) {
+        color_theme = ocean_pallette(cl);
+    }
+
+    // the mask pixel under this fragment (same nearest lookup as the sampler)
+    let dims = vec2<f32>(textureDimensions(mask_texture));
+    let mask_x_y = vec2<i32>(clamp(in.uv * dims, vec2<f32>(0.0), dims - 1.0));
+    if (textureLoad(mask_texture, mask_x_y, 0).r > 0.5) {
+        color_theme = WALL_COLOR;
+    }
     return vec4<f32>(color_theme, 1.0);
 }
 
@@ -0,0 +1,281 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use std::num::NonZeroU64;
+
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    *,
+};
+
+use crate::{
+    rd_system::{PipelineKind, WG_X, WG_Y, load_ablsolute_path},
+    shader_check::{self, ShaderError},
+};
+
+// what a stroke does to the pixels under the brush
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushMode {
+    PaintV,
+    PaintU,
+    // back to the U = 1, V = 0 base state
+    Erase,
+    // into the mask, hard edged over the whole radius, strength and falloff do not apply
+    Wall,
+    ClearWall,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrushSettings {
+    pub radius: f32,   // in grid pixels
+    pub strength: f32, // 0..1, 1 = the pixel is set to the target
+    pub falloff: f32,  // 0 = hard edge, 1 = fades out from the center
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self {
+            radius: 12.0,
+            strength: 1.0,
+            falloff: 0.5,
+        }
+    }
+}
+
+// one piece of a drag from `from` to `to` in grid coordinates
+// a single click is a stroke with from == to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrushStroke {
+    pub from: [f32; 2],
+    pub to: [f32; 2],
+    pub mode: BrushMode,
+    pub settings: BrushSettings,
+}
+
+impl BrushStroke {
+    // pixel rectangle (origin, size) the stroke can touch, None if it misses the grid
+    pub fn bounds(&self, width: u32, height: u32) -> Option<([u32; 2], [u32; 2])> {
+        let r = self.settings.radius.max(0.0) + 1.0;
+        let x0 = (self.from[0].min(self.to[0]) - r).floor().max(0.0);
+        let y0 = (self.from[1].min(self.to[1]) - r).floor().max(0.0);
+        let x1 = (self.from[0].max(self.to[0]) + r).ceil().min(width as f32);
+        let y1 = (self.from[1].max(self.to[1]) + r).ceil().min(height as f32);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+
+        let origin = [x0 as u32, y0 as u32];
+        let size = [x1 as u32 - origin[0], y1 as u32 - origin[1]];
+        Some((origin, size))
+    }
+}
+
+// cursor position in window pixels (origin top left) to grid coordinates
+// the field is stretched over the whole window and texture row 0 is drawn at the bottom
+pub fn window_to_grid(position: (f64, f64), window: (u32, u32), grid: (u32, u32)) -> [f32; 2] {
+    let x = position.0 / window.0.max(1) as f64 * grid.0 as f64;
+    let y = (1.0 - position.1 / window.1.max(1) as f64) * grid.1 as f64;
+    [x as f32, y as f32]
+}
+
+// brush as the GPU sees it, group 0 binding 0 of the brush shader
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub(crate) struct BrushUniform {
+    start: [f32; 2],  // 8 byte
+    end: [f32; 2],    // 8 byte
+    radius: f32,      // 4 byte
+    strength: f32,    // 4 byte
+    falloff: f32,     // 4 byte
+    mode: u32,        // 4 byte
+    origin: [u32; 2], // 8 byte
+    size: [u32; 2],   // 8 byte
+}
+
+impl BrushUniform {
+    pub(crate) fn new(stroke: &BrushStroke, origin: [u32; 2], size: [u32; 2]) -> Self {
+        Self {
+            start: stroke.from,
+            end: stroke.to,
+            radius: stroke.settings.radius.max(0.0),
+            strength: stroke.settings.strength.clamp(0.0, 1.0),
+            falloff: stroke.settings.falloff.clamp(0.0, 1.0),
+            mode: match stroke.mode {
+                BrushMode::PaintV => 0,
+                BrushMode::PaintU => 1,
+                BrushMode::Erase => 2,
+                BrushMode::Wall => 3,
+                BrushMode::ClearWall => 4,
+            },
+            origin,
+            size,
+        }
+    }
+}
+
+// GPU side of the brush
+// a stroke copies the touched rectangle of the current source into the other texture,
+// reads that copy and writes the painted pixels back into the current source
+// wall strokes only write the mask texture
+pub struct BrushPass {
+    pub params_buffer: Buffer,
+    pub bgl: BindGroupLayout,
+    // read 2 and write 1, read 1 and write 2
+    pub bg_into_1: BindGroup,
+    pub bg_into_2: BindGroup,
+    pub pipeline: ComputePipeline,
+}
+
+impl BrushPass {
+    pub fn new(
+        device: &Device,
+        texture_view_1: &TextureView,
+        texture_view_2: &TextureView,
+        mask_texture_view: &TextureView,
+    ) -> Result<Self, ShaderError> {
+        let params_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Brush Uniform Buffer"),
+            contents: bytemuck::bytes_of(&BrushUniform::zeroed()),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Brush Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    // brush uniform
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(
+                            std::mem::size_of::<BrushUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    // copy of the current source (sampled)
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    // current source (storage)
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    // walls (storage)
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::R32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let create_bg = |label: &str, read: &TextureView, write: &TextureView| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some(label),
+                layout: &bgl,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(read),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(write),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(mask_texture_view),
+                    },
+                ],
+            })
+        };
+        let bg_into_1 = create_bg("Brush Bind Group into 1", texture_view_2, texture_view_1);
+        let bg_into_2 = create_bg("Brush Bind Group into 2", texture_view_1, texture_view_2);
+
+        let shader_source =
+            load_ablsolute_path(PipelineKind::Brush.shader_path(), &shader_defines())?;
+        let pipeline =
+            shader_check::create_checked(device, PipelineKind::Brush.shader_path(), || {
+                create_pipeline(device, &bgl, shader_source)
+            })?;
+
+        Ok(Self {
+            params_buffer,
+            bgl,
+            bg_into_1,
+            bg_into_2,
+            pipeline,
+        })
+    }
+
+    // keeps the old pipeline if the shader does not compile
+    pub fn reload_pipeline(&mut self, device: &Device) -> Result<(), ShaderError> {
+        self.pipeline = shader_check::load_checked(
+            device,
+            PipelineKind::Brush.shader_path(),
+            &shader_defines(),
+            |source| create_pipeline(device, &self.bgl, source),
+        )?;
+        Ok(())
+    }
+}
+
+// the brush shader always runs with the default workgroup size
+pub(crate) fn shader_defines() -> Vec<(&'static str, String)> {
+    vec![
+        ("WG_X", format!("{}u", WG_X)),
+        ("WG_Y", format!("{}u", WG_Y)),
+    ]
+}
+
+fn create_pipeline(
+    device: &Device,
+    bgl: &BindGroupLayout,
+    shader_source: String,
+) -> ComputePipeline {
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("Brush Shader Module"),
+        source: ShaderSource::Wgsl(shader_source.into()),
+    });
+
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Brush Pipeline Layout"),
+        bind_group_layouts: &[bgl],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("Brush Pipeline"),
+        layout: Some(&layout),
+        module: &shader,
+        entry_point: Some("main"),
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
@@ -0,0 +1,425 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+};
+
+use crate::rd_system::{self, Boundary, Integrator, SimParams, Stencil};
+
+// file layout (all little endian)
+//   magic "RDCK", version u32
+//   width u32, height u32, use_1_as_source u32, step_count u64
+//   du, dv, feed, kill, dt f32
+//   has_param_map u32
+//   has_mask u32 (since version 2)
+//   boundary u32 (0 neumann, 1 periodic, 2 dirichlet), dirichlet u, v f32 (since version 3)
+//   stencil u32 (index into Stencil::ALL) (since version 3)
+//   integrator u32 (0 euler, 1 heun, 2 rk4, 3 imex), imex iterations u32 (since version 3)
+//   field: width * height * 4 f32 (RGBA, R = U, G = V)
+//   param map: width * height * 4 f32 (only if has_param_map)
+//   mask: width * height f32, 1 = wall (only if has_mask)
+const MAGIC: &[u8; 4] = b"RDCK";
+const VERSION: u32 = 3;
+// files without walls or without boundary, stencil and integrator can still be loaded,
+// the missing ones are the defaults
+const OLDEST_VERSION: u32 = 1;
+
+// everything needed to continue a run
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    pub use_1_as_source: bool,
+    pub step_count: u64,
+    pub params: SimParams,
+    pub boundary: Boundary,
+    pub stencil: Stencil,
+    pub integrator: Integrator,
+    pub field: Vec<f32>,
+    pub param_map: Option<Vec<f32>>,
+    pub mask: Option<Vec<f32>>,
+}
+
+impl Checkpoint {
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&self.width.to_le_bytes());
+        header.extend_from_slice(&self.height.to_le_bytes());
+        header.extend_from_slice(&(self.use_1_as_source as u32).to_le_bytes());
+        header.extend_from_slice(&self.step_count.to_le_bytes());
+        for value in params_to_array(&self.params) {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&(self.param_map.is_some() as u32).to_le_bytes());
+        header.extend_from_slice(&(self.mask.is_some() as u32).to_le_bytes());
+        let (boundary, [u, v]) = match self.boundary {
+            Boundary::Neumann => (0_u32, [0.0_f32; 2]),
+            Boundary::Periodic => (1, [0.0; 2]),
+            Boundary::Dirichlet { u, v } => (2, [u, v]),
+        };
+        header.extend_from_slice(&boundary.to_le_bytes());
+        header.extend_from_slice(&u.to_le_bytes());
+        header.extend_from_slice(&v.to_le_bytes());
+        let stencil = Stencil::ALL.iter().position(|s| *s == self.stencil);
+        header.extend_from_slice(&(stencil.unwrap_or(0) as u32).to_le_bytes());
+        let (integrator, iterations) = match self.integrator {
+            Integrator::Euler => (0_u32, 0_u32),
+            Integrator::Heun => (1, 0),
+            Integrator::Rk4 => (2, 0),
+            Integrator::Imex { iterations } => (3, iterations),
+        };
+        header.extend_from_slice(&integrator.to_le_bytes());
+        header.extend_from_slice(&iterations.to_le_bytes());
+
+        let write_error = |e: std::io::Error| format!("Failed to write {:?}: {}", path, e);
+        writer.write_all(&header).map_err(write_error)?;
+        write_f32s(&mut writer, &self.field).map_err(write_error)?;
+        if let Some(map) = &self.param_map {
+            write_f32s(&mut writer, map).map_err(write_error)?;
+        }
+        if let Some(mask) = &self.mask {
+            write_f32s(&mut writer, mask).map_err(write_error)?;
+        }
+        writer.flush().map_err(write_error)
+    }
+
+    // the header is checked against the file length and max_dimension (the device's
+    // max_texture_dimension_2d) before anything is allocated, the file can be broken or hostile
+    pub fn load(path: &Path, max_dimension: u32) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+        let file_length = file
+            .metadata()
+            .map_err(|e| format!("Failed to open {:?}: {}", path, e))?
+            .len();
+        let mut reader = BufReader::new(file);
+        let read_error = |e: std::io::Error| format!("Failed to read checkpoint {:?}: {}", path, e);
+
+        let mut magic = [0_u8; 4];
+        reader.read_exact(&mut magic).map_err(read_error)?;
+        if &magic != MAGIC {
+            return Err(format!("{:?} is not a checkpoint file", path));
+        }
+        let version = read_u32(&mut reader).map_err(read_error)?;
+        if !(OLDEST_VERSION..=VERSION).contains(&version) {
+            return Err(format!(
+                "Checkpoint {:?} has version {} but only {} to {} are supported",
+                path, version, OLDEST_VERSION, VERSION
+            ));
+        }
+
+        let width = read_u32(&mut reader).map_err(read_error)?;
+        let height = read_u32(&mut reader).map_err(read_error)?;
+        rd_system::validate_grid_size(width, height, max_dimension)
+            .map_err(|e| format!("Checkpoint {:?}: {}", path, e))?;
+        let use_1_as_source = read_u32(&mut reader).map_err(read_error)? != 0;
+        let mut step_count = [0_u8; 8];
+        reader.read_exact(&mut step_count).map_err(read_error)?;
+        let step_count = u64::from_le_bytes(step_count);
+
+        let mut params = [0.0_f32; 5];
+        for value in params.iter_mut() {
+            *value = f32::from_bits(read_u32(&mut reader).map_err(read_error)?);
+        }
+        let has_param_map = read_u32(&mut reader).map_err(read_error)? != 0;
+        let has_mask = version >= 2 && read_u32(&mut reader).map_err(read_error)? != 0;
+        let (boundary, stencil, integrator) = if version >= 3 {
+            let mut values = [0_u32; 6];
+            for value in values.iter_mut() {
+                *value = read_u32(&mut reader).map_err(read_error)?;
+            }
+            let [boundary, u, v, stencil, integrator, iterations] = values;
+            let invalid = |what: &str, value: u32| {
+                format!("Checkpoint {:?} has an unknown {} {}", path, what, value)
+            };
+            let boundary = match boundary {
+                0 => Boundary::Neumann,
+                1 => Boundary::Periodic,
+                2 => Boundary::Dirichlet {
+                    u: f32::from_bits(u),
+                    v: f32::from_bits(v),
+                },
+                _ => return Err(invalid("boundary", boundary)),
+            };
+            let stencil = *Stencil::ALL
+                .get(stencil as usize)
+                .ok_or_else(|| invalid("stencil", stencil))?;
+            let integrator = match integrator {
+                0 => Integrator::Euler,
+                1 => Integrator::Heun,
+                2 => Integrator::Rk4,
+                3 if iterations > 0 => Integrator::Imex { iterations },
+                _ => return Err(invalid("integrator", integrator)),
+            };
+            (boundary, stencil, integrator)
+        } else {
+            Default::default()
+        };
+
+        // both sides fit into u64 easily, width and height are at most max_dimension
+        let pixels = width as u64 * height as u64;
+        let expected = pixels * 16 * (1 + has_param_map as u64) + pixels * 4 * has_mask as u64;
+        let header = reader.stream_position().map_err(read_error)?;
+        if file_length.saturating_sub(header) != expected {
+            return Err(format!(
+                "Checkpoint {:?} is broken: a {}x{} grid needs {} bytes of data, the file has {}",
+                path,
+                width,
+                height,
+                expected,
+                file_length.saturating_sub(header)
+            ));
+        }
+
+        let values = (width as usize) * (height as usize) * 4;
+        let field = read_f32s(&mut reader, values).map_err(read_error)?;
+        let param_map = if has_param_map {
+            Some(read_f32s(&mut reader, values).map_err(read_error)?)
+        } else {
+            None
+        };
+        let mask = if has_mask {
+            let pixels = (width as usize) * (height as usize);
+            Some(read_f32s(&mut reader, pixels).map_err(read_error)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            width,
+            height,
+            use_1_as_source,
+            step_count,
+            params: params_from_array(params),
+            boundary,
+            stencil,
+            integrator,
+            field,
+            param_map,
+            mask,
+        })
+    }
+}
+
+fn params_to_array(params: &SimParams) -> [f32; 5] {
+    [params.du, params.dv, params.feed, params.kill, params.dt]
+}
+
+fn params_from_array(values: [f32; 5]) -> SimParams {
+    SimParams {
+        du: values[0],
+        dv: values[1],
+        feed: values[2],
+        kill: values[3],
+        dt: values[4],
+    }
+}
+
+fn write_f32s(writer: &mut impl Write, values: &[f32]) -> std::io::Result<()> {
+    // bytemuck gives native endianness, so convert value by value
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0_u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32s(reader: &mut impl Read, count: usize) -> std::io::Result<Vec<f32>> {
+    let mut bytes = vec![0_u8; count * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    const MAX_DIMENSION: u32 = 8192;
+
+    const PARAMS: SimParams = SimParams {
+        du: 0.2,
+        dv: 0.1,
+        feed: 0.04,
+        kill: 0.06,
+        dt: 0.5,
+    };
+
+    // one file per test and process, the tests run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rd_checkpoint_{}_{}.rdck",
+            std::process::id(),
+            name
+        ))
+    }
+
+    // 3x2 grid with every optional part and non-default settings
+    fn checkpoint() -> Checkpoint {
+        let pixels = 6;
+        Checkpoint {
+            width: 3,
+            height: 2,
+            use_1_as_source: false,
+            step_count: 123_456_789_012,
+            params: PARAMS,
+            boundary: Boundary::Dirichlet { u: 0.9, v: 0.2 },
+            stencil: Stencil::FourthOrder,
+            integrator: Integrator::Imex { iterations: 7 },
+            field: (0..pixels * 4).map(|i| i as f32 * 0.25).collect(),
+            param_map: Some((0..pixels * 4).map(|i| i as f32 * 0.01).collect()),
+            mask: Some(vec![0.0, 1.0, 0.0, 0.0, 1.0, 1.0]),
+        }
+    }
+
+    fn load_error(path: &Path) -> String {
+        match Checkpoint::load(path, MAX_DIMENSION) {
+            Ok(_) => panic!("{:?} should not load", path),
+            Err(e) => e,
+        }
+    }
+
+    // the file layout up to the version 1 part of the header, written by hand
+    fn v1_header(version: u32, width: u32, height: u32, has_param_map: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(&1_u32.to_le_bytes());
+        bytes.extend_from_slice(&42_u64.to_le_bytes());
+        for value in params_to_array(&PARAMS) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(has_param_map as u32).to_le_bytes());
+        bytes
+    }
+
+    fn push_f32s(bytes: &mut Vec<u8>, values: &[f32]) {
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round_trip");
+        let saved = checkpoint();
+        saved.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path, MAX_DIMENSION).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert!(!loaded.use_1_as_source);
+        assert_eq!(loaded.step_count, saved.step_count);
+        assert_eq!(loaded.params, saved.params);
+        assert_eq!(loaded.boundary, saved.boundary);
+        assert_eq!(loaded.stencil, saved.stencil);
+        assert_eq!(loaded.integrator, saved.integrator);
+        assert_eq!(loaded.field, saved.field);
+        assert_eq!(loaded.param_map, saved.param_map);
+        assert_eq!(loaded.mask, saved.mask);
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let path = temp_path("truncated");
+        checkpoint().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        let error = load_error(&path);
+        // cut inside the header
+        fs::write(&path, &bytes[..20]).unwrap();
+        let header_error = load_error(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(error.contains("is broken"), "{}", error);
+        assert!(header_error.contains("Failed to read"), "{}", header_error);
+    }
+
+    #[test]
+    fn bad_header_is_rejected() {
+        let path = temp_path("bad_header");
+        let cases: [(Vec<u8>, &str); 5] = [
+            (b"PNG\0".to_vec(), "is not a checkpoint file"),
+            (v1_header(VERSION + 1, 3, 2, false), "are supported"),
+            (v1_header(1, 0, 2, false), "Grid size 0x2 is invalid"),
+            (v1_header(1, MAX_DIMENSION + 1, 2, false), "is invalid"),
+            // a huge grid in a tiny file fails before anything is allocated
+            (
+                v1_header(1, MAX_DIMENSION, MAX_DIMENSION, true),
+                "is broken",
+            ),
+        ];
+        for (bytes, expected) in cases {
+            fs::write(&path, &bytes).unwrap();
+            let error = load_error(&path);
+            assert!(error.contains(expected), "{}", error);
+        }
+
+        // unknown boundary, stencil and integrator in a version 3 header
+        let mut values = [0_u32; 6];
+        for (index, name) in [(0, "boundary"), (3, "stencil"), (4, "integrator")] {
+            values[index] = 99;
+            let mut bytes = v1_header(3, 1, 1, false);
+            bytes.extend_from_slice(&0_u32.to_le_bytes());
+            for value in values {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            push_f32s(&mut bytes, &[0.0; 4]);
+            fs::write(&path, &bytes).unwrap();
+            let error = load_error(&path);
+            assert!(error.contains(&format!("unknown {} 99", name)), "{}", error);
+            values[index] = 0;
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn version_1_and_2_files_still_load() {
+        let path = temp_path("old_versions");
+        let field: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let param_map = vec![0.5; 8];
+
+        // version 1: no walls and no boundary, stencil or integrator
+        let mut v1 = v1_header(1, 2, 1, true);
+        push_f32s(&mut v1, &field);
+        push_f32s(&mut v1, &param_map);
+        fs::write(&path, &v1).unwrap();
+        let loaded = Checkpoint::load(&path, MAX_DIMENSION).unwrap();
+        assert_eq!((loaded.width, loaded.height), (2, 1));
+        assert!(loaded.use_1_as_source);
+        assert_eq!(loaded.step_count, 42);
+        assert_eq!(loaded.params, PARAMS);
+        assert_eq!(loaded.field, field);
+        assert_eq!(loaded.param_map, Some(param_map.clone()));
+        assert_eq!(loaded.mask, None);
+        assert_eq!(loaded.boundary, Boundary::default());
+        assert_eq!(loaded.stencil, Stencil::default());
+        assert_eq!(loaded.integrator, Integrator::default());
+
+        // version 2: walls after the param map
+        let mut v2 = v1_header(2, 2, 1, false);
+        v2.extend_from_slice(&1_u32.to_le_bytes());
+        push_f32s(&mut v2, &field);
+        push_f32s(&mut v2, &[1.0, 0.0]);
+        fs::write(&path, &v2).unwrap();
+        let loaded = Checkpoint::load(&path, MAX_DIMENSION).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.field, field);
+        assert_eq!(loaded.param_map, None);
+        assert_eq!(loaded.mask, Some(vec![1.0, 0.0]));
+        assert_eq!(loaded.integrator, Integrator::default());
+    }
+}
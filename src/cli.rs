@@ -0,0 +1,346 @@
+use std::path::PathBuf;
+
+use reaction_diffusion_wgpu::{
+    Boundary, DEFAULT_HEIGHT, DEFAULT_WIDTH, Integrator, Palette, SimParams, Stencil,
+    brush::BrushSettings,
+    clock::MAX_STEPS_PER_FRAME,
+    initial_conditions::InitialCondition,
+    mask::MaskShape,
+    param_map::ParamMapSource,
+    presets::{PRESETS, Preset},
+    rd_system::{self, MAX_GRID_DIMENSION, WG_X, WG_Y},
+    scene::{PresentMode, Scene},
+    stability::StabilitySettings,
+};
+
+pub const USAGE: &str = "\
+Usage: reaction_diffusion_wgpu [OPTIONS]
+
+Options:
+  --scene <PATH>     load a TOML scene (grid, params, init, display, brush),
+                     options after it override the scene. S in the window saves
+                     the live state back to it
+  --save-scene <PATH> headless: write the final state as a scene
+  --size <WxH>       grid size in pixels (default 1280x1280)
+  --init <NAME>      initial condition: blob, noise, spots, squares, stripes,
+                     ring, checkerboard, pearson, image:<path.png> or
+                     text:<text> (default blob)
+  --preset <NAME>    known Gray-Scott regime: Pearson's alpha .. mu, mitosis,
+                     coral, worms or solitons. Sets the parameters and, without
+                     --init, the initial condition
+  --init-u           image/text initial conditions also set U = 1 - luminance
+  --seed <N>         seed for the random initial conditions (default 0)
+  --boundary <NAME>  what the laplacian sees outside the grid: neumann (zero
+                     flux, default), periodic (seamless tiles), dirichlet
+                     (U = 1, V = 0 outside) or dirichlet:<U>,<V>
+  --stencil <NAME>   laplacian: five-point (default), isotropic, oono-puri,
+                     sims or fourth-order
+  --integrator <NAME> time stepping: euler (default), heun, rk4, imex (implicit
+                     diffusion, 20 Jacobi iterations) or imex:<iterations>
+  --auto-dt          clamp dt below the stable limit instead of only warning
+  --detect-divergence stop (headless) or pause (window) when the field turns
+                     NaN or leaves [0, 1]
+  --mask <SHAPE>     walls around circle, image:<path.png> (bright = inside) or
+                     text:<text>, the reaction only runs inside
+  --mask-invert      walls inside the mask shape instead (obstacles)
+  --checkpoint <PATH> resume from a checkpoint file
+  --palette <NAME>   cosine, grayscale, fire or ocean (default cosine)
+  --steps-per-frame <N> compute steps per displayed frame in the window (default 1)
+  --paused           start the window paused
+  --headless         run without a window and export the last frame
+  --steps <N>        compute steps in headless mode (default 1000)
+  --output <PATH>    PNG written in headless mode (default frame.png)
+  --save-checkpoint <PATH>  headless: write a checkpoint after the last step
+  --record <DIR>     headless: write a PNG sequence and recording.gif to DIR
+  --record-every <N> steps between recorded frames (default 10, also used by R in the window)
+  --brush-radius <X>   brush radius in grid pixels (default 12)
+  --brush-strength <X> 0..1, how strongly one stroke paints (default 1)
+  --brush-falloff <X>  0 = hard edge, 1 = soft from the center (default 0.5)
+  --validate         compare the headless GPU result with the CPU reference
+  --tolerance <X>    largest allowed U/V difference for --validate (default 0.001)
+  --param-map <MAP>  feed and kill per pixel: gradient (feed bottom to top,
+                     kill left to right) or image:<path.png> (red = feed,
+                     green = kill)
+  --param-range <F0>,<F1>:<K0>,<K1>  feed and kill ranges of the parameter map
+                     (default 0.01,0.1:0.045,0.07)
+  -h, --help         print this help";
+
+// command line options
+pub struct Options {
+    pub width: u32,
+    pub height: u32,
+    pub workgroup_size: [u32; 2],
+    pub present_mode: PresentMode,
+    pub scene: Option<PathBuf>,
+    pub save_scene: Option<PathBuf>,
+    pub init: InitialCondition,
+    pub init_u: bool,
+    pub params: SimParams,
+    // index into PRESETS
+    pub preset: Option<usize>,
+    pub seed: u64,
+    pub boundary: Boundary,
+    pub stencil: Stencil,
+    pub integrator: Integrator,
+    pub stability: StabilitySettings,
+    pub mask: Option<MaskShape>,
+    pub mask_invert: bool,
+    pub checkpoint: Option<PathBuf>,
+    pub save_checkpoint: Option<PathBuf>,
+    pub palette: Palette,
+    pub steps_per_frame: u32,
+    pub paused: bool,
+    pub headless: bool,
+    pub steps: u32,
+    pub output: PathBuf,
+    pub record: Option<PathBuf>,
+    pub record_every: u32,
+    pub brush: BrushSettings,
+    pub validate: bool,
+    pub tolerance: f32,
+    pub param_map: Option<ParamMapSource>,
+    // feed and kill ranges from --param-range
+    pub param_range: Option<([f32; 2], [f32; 2])>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            workgroup_size: [WG_X, WG_Y],
+            present_mode: PresentMode::default(),
+            scene: None,
+            save_scene: None,
+            init: InitialCondition::default(),
+            init_u: false,
+            params: SimParams::default(),
+            preset: None,
+            seed: 0,
+            boundary: Boundary::default(),
+            stencil: Stencil::default(),
+            integrator: Integrator::default(),
+            stability: StabilitySettings::default(),
+            mask: None,
+            mask_invert: false,
+            checkpoint: None,
+            save_checkpoint: None,
+            palette: Palette::default(),
+            steps_per_frame: 1,
+            paused: false,
+            headless: false,
+            steps: 1000,
+            output: PathBuf::from("frame.png"),
+            record: None,
+            record_every: 10,
+            brush: BrushSettings::default(),
+            validate: false,
+            tolerance: 1e-3,
+            param_map: None,
+            param_range: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn apply_scene(&mut self, scene: Scene) {
+        self.seed = scene.seed;
+        self.width = scene.grid.width;
+        self.height = scene.grid.height;
+        self.workgroup_size = scene.grid.workgroup_size;
+        self.stencil = scene.grid.stencil;
+        self.params = scene.params;
+        self.preset = None;
+        self.init = scene.init;
+        self.boundary = scene.boundary;
+        self.integrator = scene.integrator;
+        self.stability = scene.stability;
+        self.mask = scene.mask;
+        self.param_map = scene.param_map;
+        self.palette = scene.display.palette;
+        self.present_mode = scene.display.present_mode;
+        self.steps_per_frame = scene.display.steps_per_frame;
+        self.brush = scene.brush;
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    let mut init_given = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => {
+                let path = PathBuf::from(next_value(&mut args, &arg)?);
+                options.apply_scene(Scene::load(&path)?);
+                options.scene = Some(path);
+                init_given = true;
+            }
+            "--save-scene" => {
+                options.save_scene = Some(PathBuf::from(next_value(&mut args, &arg)?))
+            }
+            "--size" => {
+                let value = next_value(&mut args, &arg)?;
+                (options.width, options.height) = parse_size(&value)?;
+            }
+            "--init" => {
+                options.init = InitialCondition::parse(&next_value(&mut args, &arg)?)?;
+                init_given = true;
+            }
+            "--preset" => {
+                let name = next_value(&mut args, &arg)?;
+                let index = PRESETS.iter().position(|p| p.name == name).ok_or_else(|| {
+                    format!(
+                        "Unknown preset '{}', expected one of {}",
+                        name,
+                        Preset::names().join(", ")
+                    )
+                })?;
+                let preset = &PRESETS[index];
+                options.params = preset.params;
+                options.preset = Some(index);
+                if !init_given {
+                    options.init = preset.initial_condition();
+                }
+            }
+            "--init-u" => options.init_u = true,
+            "--seed" => options.seed = parse_number(&next_value(&mut args, &arg)?, &arg)?,
+            "--boundary" => options.boundary = Boundary::parse(&next_value(&mut args, &arg)?)?,
+            "--integrator" => {
+                options.integrator = Integrator::parse(&next_value(&mut args, &arg)?)?
+            }
+            "--auto-dt" => options.stability.auto_dt = true,
+            "--detect-divergence" => options.stability.detect_divergence = true,
+            "--stencil" => {
+                let name = next_value(&mut args, &arg)?;
+                options.stencil = Stencil::from_name(&name).ok_or_else(|| {
+                    let names: Vec<&str> = Stencil::ALL.iter().map(|s| s.name()).collect();
+                    format!(
+                        "Unknown stencil '{}', expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?;
+            }
+            "--mask" => options.mask = Some(MaskShape::parse(&next_value(&mut args, &arg)?)?),
+            "--mask-invert" => options.mask_invert = true,
+            "--checkpoint" => {
+                options.checkpoint = Some(PathBuf::from(next_value(&mut args, &arg)?))
+            }
+            "--save-checkpoint" => {
+                options.save_checkpoint = Some(PathBuf::from(next_value(&mut args, &arg)?))
+            }
+            "--steps-per-frame" => {
+                options.steps_per_frame = parse_number(&next_value(&mut args, &arg)?, &arg)?;
+                if !(1..=MAX_STEPS_PER_FRAME).contains(&options.steps_per_frame) {
+                    return Err(format!(
+                        "'--steps-per-frame' must be between 1 and {}",
+                        MAX_STEPS_PER_FRAME
+                    ));
+                }
+            }
+            "--paused" => options.paused = true,
+            "--palette" => {
+                let name = next_value(&mut args, &arg)?;
+                options.palette = Palette::from_name(&name).ok_or_else(|| {
+                    let names: Vec<&str> = Palette::ALL.iter().map(|p| p.name()).collect();
+                    format!(
+                        "Unknown palette '{}', expected one of {}",
+                        name,
+                        names.join(", ")
+                    )
+                })?;
+            }
+            "--headless" => options.headless = true,
+            "--steps" => options.steps = parse_number(&next_value(&mut args, &arg)?, &arg)?,
+            "--output" => options.output = PathBuf::from(next_value(&mut args, &arg)?),
+            "--record" => options.record = Some(PathBuf::from(next_value(&mut args, &arg)?)),
+            "--record-every" => {
+                options.record_every = parse_number(&next_value(&mut args, &arg)?, &arg)?;
+                if options.record_every == 0 {
+                    return Err("'--record-every' must be at least 1".to_string());
+                }
+            }
+            "--brush-radius" => {
+                options.brush.radius = parse_number(&next_value(&mut args, &arg)?, &arg)?
+            }
+            "--brush-strength" => {
+                options.brush.strength = parse_number(&next_value(&mut args, &arg)?, &arg)?
+            }
+            "--brush-falloff" => {
+                options.brush.falloff = parse_number(&next_value(&mut args, &arg)?, &arg)?
+            }
+            "--validate" => options.validate = true,
+            "--tolerance" => options.tolerance = parse_number(&next_value(&mut args, &arg)?, &arg)?,
+            "--param-map" => {
+                options.param_map = Some(ParamMapSource::parse(&next_value(&mut args, &arg)?)?)
+            }
+            "--param-range" => {
+                options.param_range =
+                    Some(ParamMapSource::parse_ranges(&next_value(&mut args, &arg)?)?)
+            }
+            "-h" | "--help" => options.help = true,
+            _ => return Err(format!("Unknown option '{}'", arg)),
+        }
+    }
+
+    if let Some((feed, kill)) = options.param_range {
+        match &mut options.param_map {
+            Some(param_map) => {
+                param_map.set_ranges(feed, kill);
+                param_map
+                    .validate()
+                    .map_err(|e| format!("Invalid '--param-range': {}", e))?;
+            }
+            None => return Err("'--param-range' needs a '--param-map'".to_string()),
+        }
+    }
+
+    if options.init_u {
+        match &mut options.init {
+            InitialCondition::Image { map_u, .. } | InitialCondition::Text { map_u, .. } => {
+                *map_u = true
+            }
+            _ => {
+                return Err(
+                    "'--init-u' only works with image: and text: initial conditions".to_string(),
+                );
+            }
+        }
+    }
+
+    if options.mask_invert {
+        match &mut options.mask {
+            Some(mask) => mask.set_invert(true),
+            None => return Err("'--mask-invert' needs a '--mask'".to_string()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Option '{}' needs a value", option))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, option: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, option))
+}
+
+// "640x480"
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let (w, h) = value
+        .split_once('x')
+        .ok_or_else(|| format!("Invalid size '{}', expected WxH", value))?;
+    let width: u32 = parse_number(w, "--size")?;
+    let height: u32 = parse_number(h, "--size")?;
+    rd_system::validate_grid_size(width, height, MAX_GRID_DIMENSION)
+        .map_err(|e| format!("Invalid size '{}': {}", value, e))?;
+    Ok((width, height))
+}
@@ -0,0 +1,56 @@
+// decides how many compute steps go into each displayed frame
+
+// more than this per frame makes the window unresponsive on big grids
+pub const MAX_STEPS_PER_FRAME: u32 = 256;
+
+pub struct SimClock {
+    pub paused: bool,
+    // compute dispatches per displayed frame while running
+    pub steps_per_frame: u32,
+    // single steps requested while paused
+    pending_steps: u32,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl SimClock {
+    pub fn new(steps_per_frame: u32) -> Self {
+        Self {
+            paused: false,
+            steps_per_frame: steps_per_frame.clamp(1, MAX_STEPS_PER_FRAME),
+            pending_steps: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.pending_steps = 0;
+    }
+
+    // pauses and advances exactly one step with the next frame
+    pub fn single_step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    pub fn faster(&mut self) {
+        self.steps_per_frame = (self.steps_per_frame * 2).min(MAX_STEPS_PER_FRAME);
+    }
+
+    pub fn slower(&mut self) {
+        self.steps_per_frame = (self.steps_per_frame / 2).max(1);
+    }
+
+    // call once per displayed frame
+    pub fn steps_for_frame(&mut self) -> u32 {
+        if self.paused {
+            std::mem::take(&mut self.pending_steps)
+        } else {
+            self.steps_per_frame
+        }
+    }
+}
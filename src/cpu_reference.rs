@@ -0,0 +1,574 @@
+use crate::{
+    mask::Mask,
+    param_map::ParamMap,
+    rd_system::{Boundary, Integrator, SimParams, Stage, StageKind, Stencil},
+};
+
+// Pure Rust version of the Gray-Scott step in rd_compute.wgsl
+// same laplacian stencils, boundary conditions, walls, integrators and clamping to [0, 1]
+// the field has the RGBA f32 layout of the GPU textures (R = U, G = V, B = 0, A = 1)
+// and so have the stage textures between the stages of a step
+pub struct CpuSimulation {
+    pub width: u32,
+    pub height: u32,
+    pub params: SimParams,
+    pub param_map: Option<ParamMap>,
+    pub boundary: Boundary,
+    pub stencil: Stencil,
+    pub integrator: Integrator,
+    pub mask: Option<Mask>,
+    pub field: Vec<f32>,
+    // second buffer so one is read while the other is written (like the GPU ping-pong)
+    scratch: Vec<f32>,
+    pub step_count: u64,
+}
+
+impl CpuSimulation {
+    pub fn new(width: u32, height: u32, field: Vec<f32>) -> Result<Self, String> {
+        let expected = (width * height * 4) as usize;
+        if field.len() != expected {
+            return Err(format!(
+                "Field has {} values but the grid needs {}",
+                field.len(),
+                expected
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            params: SimParams::default(),
+            param_map: None,
+            boundary: Boundary::default(),
+            stencil: Stencil::default(),
+            integrator: Integrator::default(),
+            mask: None,
+            scratch: field.clone(),
+            field,
+            step_count: 0,
+        })
+    }
+
+    pub fn step(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.step_once();
+        }
+    }
+
+    // every stage of the integrator over the whole grid, like the dispatches on the GPU
+    fn step_once(&mut self) {
+        let mut field_out = std::mem::take(&mut self.scratch);
+        let mut stage_in = Vec::new();
+        for stage in self.integrator.stages() {
+            let mut out = match stage.last {
+                true => std::mem::take(&mut field_out),
+                false => vec![0.0; self.field.len()],
+            };
+            for y in 0..self.height as i32 {
+                for x in 0..self.width as i32 {
+                    let idx = self.index(x, y);
+                    out[idx..idx + 4].copy_from_slice(&self.stage_pixel(&stage, &stage_in, x, y));
+                }
+            }
+            match stage.last {
+                true => field_out = out,
+                false => stage_in = out,
+            }
+        }
+
+        self.scratch = std::mem::replace(&mut self.field, field_out);
+        self.step_count += 1;
+    }
+
+    // what the stage writes at x, y, `stage_in` is what the stage before wrote
+    fn stage_pixel(&self, stage: &Stage, stage_in: &[f32], x: i32, y: i32) -> [f32; 4] {
+        // nothing grows inside a wall
+        if self.is_wall(x, y) {
+            return match stage.last {
+                true => [1.0, 0.0, 0.0, 1.0],
+                false => [1.0, 0.0, 1.0, 0.0],
+            };
+        }
+
+        let dt = self.params.dt;
+        let idx = self.index(x, y);
+        let params = self.local_params(idx);
+        // the field at the start of the step
+        let start = self.read_u_v(&self.field, x, y);
+        let clamped = |u_v: (f32, f32)| [u_v.0.clamp(0.0, 1.0), u_v.1.clamp(0.0, 1.0), 0.0, 1.0];
+
+        match stage.kind {
+            StageKind::RungeKutta => {
+                let (state, mut rates, lap) = match stage.first {
+                    true => (start, (0.0, 0.0), self.laplacian(&self.field, x, y)),
+                    false => (
+                        (stage_in[idx], stage_in[idx + 1]),
+                        (stage_in[idx + 2], stage_in[idx + 3]),
+                        self.laplacian(stage_in, x, y),
+                    ),
+                };
+                let k = rate(state, lap, params);
+                rates.0 += stage.weight * k.0;
+                rates.1 += stage.weight * k.1;
+
+                match stage.last {
+                    true => clamped((start.0 + rates.0 * dt, start.1 + rates.1 * dt)),
+                    false => [
+                        start.0 + stage.next_offset * dt * k.0,
+                        start.1 + stage.next_offset * dt * k.1,
+                        rates.0,
+                        rates.1,
+                    ],
+                }
+            }
+            StageKind::ImexReaction => {
+                let r = rate(start, (0.0, 0.0), params);
+                let rhs = (start.0 + dt * r.0, start.1 + dt * r.1);
+                [rhs.0, rhs.1, rhs.0, rhs.1]
+            }
+            StageKind::ImexJacobi => {
+                let center = (stage_in[idx], stage_in[idx + 1]);
+                let rhs = (stage_in[idx + 2], stage_in[idx + 3]);
+                let dt_d = (dt * params.2, dt * params.3);
+                let sum = self.neighbor_sum(stage_in, x, y, center);
+                let center_weight = self.stencil.weights().center;
+                let next = (
+                    (rhs.0 + dt_d.0 * sum.0) / (1.0 - dt_d.0 * center_weight),
+                    (rhs.1 + dt_d.1 * sum.1) / (1.0 - dt_d.1 * center_weight),
+                );
+                match stage.last {
+                    true => clamped(next),
+                    false => [next.0, next.1, rhs.0, rhs.1],
+                }
+            }
+        }
+    }
+
+    // feed, kill, du, dv from the global parameters or the parameter map
+    // du or dv below 0 in the map means the global one
+    fn local_params(&self, idx: usize) -> (f32, f32, f32, f32) {
+        let diffusion = |local: f32, global: f32| match local < 0.0 {
+            true => global,
+            false => local,
+        };
+        match &self.param_map {
+            Some(map) => (
+                map.data[idx],
+                map.data[idx + 1],
+                diffusion(map.data[idx + 2], self.params.du),
+                diffusion(map.data[idx + 3], self.params.dv),
+            ),
+            None => (
+                self.params.feed,
+                self.params.kill,
+                self.params.du,
+                self.params.dv,
+            ),
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        ((y as u32 * self.width + x as u32) * 4) as usize
+    }
+
+    // clamped to the border like read_u_v in the shader
+    // `texture` is the field or a stage texture
+    fn read_u_v(&self, texture: &[f32], x: i32, y: i32) -> (f32, f32) {
+        let x = x.clamp(0, self.width as i32 - 1);
+        let y = y.clamp(0, self.height as i32 - 1);
+        let idx = self.index(x, y);
+        (texture[idx], texture[idx + 1])
+    }
+
+    // like read_bounded in common.wgsl
+    fn read_bounded(&self, texture: &[f32], x: i32, y: i32) -> (f32, f32) {
+        let (w, h) = (self.width as i32, self.height as i32);
+        match self.boundary {
+            Boundary::Periodic => self.read_u_v(texture, x.rem_euclid(w), y.rem_euclid(h)),
+            Boundary::Dirichlet { u, v } if x < 0 || y < 0 || x >= w || y >= h => (u, v),
+            _ => self.read_u_v(texture, x, y),
+        }
+    }
+
+    // like is_wall in common.wgsl, outside the grid is never a wall
+    fn is_wall(&self, x: i32, y: i32) -> bool {
+        let Some(mask) = &self.mask else {
+            return false;
+        };
+        let (w, h) = (self.width as i32, self.height as i32);
+        let (x, y) = match self.boundary {
+            Boundary::Periodic => (x.rem_euclid(w), y.rem_euclid(h)),
+            _ => (x, y),
+        };
+        x >= 0 && y >= 0 && x < w && y < h && mask.is_wall(x as u32, y as u32)
+    }
+
+    // like read_neighbor in common.wgsl
+    fn read_neighbor(&self, texture: &[f32], x: i32, y: i32, center: (f32, f32)) -> (f32, f32) {
+        if !self.is_wall(x, y) {
+            return self.read_bounded(texture, x, y);
+        }
+        match self.boundary {
+            Boundary::Dirichlet { u, v } => (u, v),
+            _ => center,
+        }
+    }
+
+    // like neighbor_sum in common.wgsl, adding up in the same order
+    fn neighbor_sum(&self, texture: &[f32], x: i32, y: i32, center: (f32, f32)) -> (f32, f32) {
+        let weights = self.stencil.weights();
+        let mut sum = (0.0, 0.0);
+        let mut add = |weight: f32, value: (f32, f32)| {
+            sum.0 += weight * value.0;
+            sum.1 += weight * value.1;
+        };
+
+        // up, down, left, right
+        for (dx, dy) in [(0, -1), (0, 1), (-1, 0), (1, 0)] {
+            let near = self.read_neighbor(texture, x + dx, y + dy, center);
+            add(weights.edge, near);
+            if weights.far != 0.0 {
+                // two away, but not through a wall
+                let far = match self.is_wall(x + dx, y + dy) {
+                    true => near,
+                    false => self.read_neighbor(texture, x + 2 * dx, y + 2 * dy, center),
+                };
+                add(weights.far, far);
+            }
+        }
+
+        if weights.corner != 0.0 {
+            for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                add(
+                    weights.corner,
+                    self.read_neighbor(texture, x + dx, y + dy, center),
+                );
+            }
+        }
+        sum
+    }
+
+    // like laplacian in common.wgsl
+    fn laplacian(&self, texture: &[f32], x: i32, y: i32) -> (f32, f32) {
+        let center_weight = self.stencil.weights().center;
+        let center = self.read_u_v(texture, x, y);
+        let sum = self.neighbor_sum(texture, x, y, center);
+        (
+            center_weight * center.0 + sum.0,
+            center_weight * center.1 + sum.1,
+        )
+    }
+}
+
+// like rate in rd_compute.wgsl, params are (feed, kill, du, dv)
+fn rate(u_v: (f32, f32), lap: (f32, f32), params: (f32, f32, f32, f32)) -> (f32, f32) {
+    let (u, v) = u_v;
+    let (feed, kill, diff_u, diff_v) = params;
+    let du = diff_u * lap.0 - u * v * v + feed * (1.0 - u);
+    let dv = diff_v * lap.1 + u * v * v - (feed + kill) * v;
+    (du, dv)
+}
+
+// largest difference of U or V between two fields with the same layout
+// NaN counts as infinitely far away so a diverged field never passes
+pub fn max_abs_diff(a: &[f32], b: &[f32]) -> f32 {
+    a.chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .map(|(pa, pb)| {
+            // f32::max would drop a NaN on one side
+            let (du, dv) = ((pa[0] - pb[0]).abs(), (pa[1] - pb[1]).abs());
+            if du.is_nan() || dv.is_nan() {
+                f32::INFINITY
+            } else {
+                du.max(dv)
+            }
+        })
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headless::Headless, initial_conditions::InitialCondition};
+
+    const PARAMS: SimParams = SimParams {
+        du: 0.2,
+        dv: 0.1,
+        feed: 0.04,
+        kill: 0.06,
+        dt: 0.5,
+    };
+
+    const BOUNDARIES: [Boundary; 3] = [
+        Boundary::Neumann,
+        Boundary::Periodic,
+        Boundary::Dirichlet { u: 0.9, v: 0.2 },
+    ];
+
+    // two pixels side by side, U and V of each
+    type Pair = [(f32, f32); 2];
+
+    // five-point laplacian of a 2x1 grid written out for each boundary
+    // neumann reads the pixel itself outside, periodic wraps to the other one in x and
+    // to itself in y, dirichlet reads the fixed values on three sides
+    fn laplacian(boundary: Boundary, s: Pair) -> Pair {
+        let [a, b] = s;
+        let lap = |own: (f32, f32), other: (f32, f32)| match boundary {
+            Boundary::Neumann => (other.0 - own.0, other.1 - own.1),
+            Boundary::Periodic => (2.0 * (other.0 - own.0), 2.0 * (other.1 - own.1)),
+            Boundary::Dirichlet { u, v } => (
+                3.0 * u + other.0 - 4.0 * own.0,
+                3.0 * v + other.1 - 4.0 * own.1,
+            ),
+        };
+        [lap(a, b), lap(b, a)]
+    }
+
+    // Gray-Scott, diffusion only where `diffusion` is set
+    fn gray_scott(boundary: Boundary, s: Pair, diffusion: bool) -> Pair {
+        let lap = laplacian(boundary, s);
+        let f = |(u, v): (f32, f32), (lu, lv): (f32, f32)| {
+            let (lu, lv) = if diffusion { (lu, lv) } else { (0.0, 0.0) };
+            (
+                PARAMS.du * lu - u * v * v + PARAMS.feed * (1.0 - u),
+                PARAMS.dv * lv + u * v * v - (PARAMS.feed + PARAMS.kill) * v,
+            )
+        };
+        [f(s[0], lap[0]), f(s[1], lap[1])]
+    }
+
+    // s + factor * k, pixel by pixel
+    fn add(s: Pair, factor: f32, k: Pair) -> Pair {
+        [0, 1].map(|i| (s[i].0 + factor * k[i].0, s[i].1 + factor * k[i].1))
+    }
+
+    // one step done by hand from the textbook formulas
+    fn expected_step(integrator: Integrator, boundary: Boundary, s: Pair) -> Pair {
+        let dt = PARAMS.dt;
+        let f = |s: Pair| gray_scott(boundary, s, true);
+        match integrator {
+            Integrator::Euler => add(s, dt, f(s)),
+            Integrator::Heun => {
+                let k1 = f(s);
+                let k2 = f(add(s, dt, k1));
+                add(add(s, dt / 2.0, k1), dt / 2.0, k2)
+            }
+            Integrator::Rk4 => {
+                let k1 = f(s);
+                let k2 = f(add(s, dt / 2.0, k1));
+                let k3 = f(add(s, dt / 2.0, k2));
+                let k4 = f(add(s, dt, k3));
+                let sum = add(add(add(k1, 2.0, k2), 2.0, k3), 1.0, k4);
+                add(s, dt / 6.0, sum)
+            }
+            // (1 - dt D L) y = s + dt R(s), Jacobi from y = rhs
+            // the neighbors of a pixel add up to laplacian + 4 * center
+            Integrator::Imex { iterations } => {
+                let rhs = add(s, dt, gray_scott(boundary, s, false));
+                let (a_u, a_v) = (dt * PARAMS.du, dt * PARAMS.dv);
+                let mut y = rhs;
+                for _ in 0..iterations {
+                    let lap = laplacian(boundary, y);
+                    y = [0, 1].map(|i| {
+                        (
+                            (rhs[i].0 + a_u * (lap[i].0 + 4.0 * y[i].0)) / (1.0 + 4.0 * a_u),
+                            (rhs[i].1 + a_v * (lap[i].1 + 4.0 * y[i].1)) / (1.0 + 4.0 * a_v),
+                        )
+                    });
+                }
+                y
+            }
+        }
+    }
+
+    #[test]
+    fn single_step_matches_hand_computed() {
+        let start: Pair = [(0.8, 0.3), (0.5, 0.45)];
+        let field = vec![
+            start[0].0, start[0].1, 0.0, 1.0, start[1].0, start[1].1, 0.0, 1.0,
+        ];
+        let integrators = [
+            Integrator::Euler,
+            Integrator::Heun,
+            Integrator::Rk4,
+            Integrator::Imex { iterations: 3 },
+        ];
+
+        for integrator in integrators {
+            for boundary in BOUNDARIES {
+                let mut cpu = CpuSimulation::new(2, 1, field.clone()).unwrap();
+                cpu.params = PARAMS;
+                cpu.boundary = boundary;
+                cpu.integrator = integrator;
+                cpu.step(1);
+
+                let expected = expected_step(integrator, boundary, start);
+                for (i, (u, v)) in expected.into_iter().enumerate() {
+                    // no clamping needed, everything stays inside [0, 1]
+                    assert!((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v));
+                    let pixel = &cpu.field[i * 4..i * 4 + 4];
+                    assert!(
+                        (pixel[0] - u).abs() < 1e-6 && (pixel[1] - v).abs() < 1e-6,
+                        "{} with {:?}, pixel {}: got {:?}, expected ({}, {})",
+                        integrator,
+                        boundary,
+                        i,
+                        pixel,
+                        u,
+                        v
+                    );
+                    assert_eq!(&pixel[2..], &[0.0, 1.0]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn max_abs_diff_counts_nan_as_infinite() {
+        let a = [0.5, 0.5, 0.0, 1.0];
+        assert_eq!(max_abs_diff(&a, &[0.25, 0.75, 0.0, 1.0]), 0.25);
+        assert_eq!(max_abs_diff(&a, &[f32::NAN, 0.5, 0.0, 1.0]), f32::INFINITY);
+    }
+
+    // no GPU (or software adapter) in the environment, nothing to compare against
+    fn has_adapter() -> bool {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        [false, true].into_iter().any(|force_fallback_adapter| {
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter,
+                ..Default::default()
+            }))
+            .is_ok()
+        })
+    }
+
+    #[test]
+    fn gpu_matches_cpu_for_every_stencil_integrator_and_boundary() {
+        if !has_adapter() {
+            eprintln!("no adapter found, skipping the GPU comparison");
+            return;
+        }
+        let (width, height) = (24, 16);
+        let mut headless = pollster::block_on(Headless::new(width, height)).unwrap();
+        let start = InitialCondition::RandomSpots {
+            count: 6,
+            radius: 3,
+        }
+        .generate(width, height, 1)
+        .unwrap();
+        let simulation = &mut headless.simulation;
+        simulation.set_params(&PARAMS);
+
+        let integrators = [
+            Integrator::Euler,
+            Integrator::Heun,
+            Integrator::Rk4,
+            Integrator::Imex { iterations: 8 },
+        ];
+        for stencil in Stencil::ALL {
+            simulation.set_stencil(stencil).unwrap();
+            for integrator in integrators {
+                simulation.set_integrator(integrator).unwrap();
+                for boundary in BOUNDARIES {
+                    simulation.set_boundary(boundary);
+                    simulation.write_field(&start).unwrap();
+                    simulation.step(20);
+                    let gpu_field = simulation.read_field().unwrap();
+
+                    let mut cpu = CpuSimulation::new(width, height, start.clone()).unwrap();
+                    cpu.params = PARAMS;
+                    cpu.boundary = boundary;
+                    cpu.stencil = stencil;
+                    cpu.integrator = integrator;
+                    cpu.step(20);
+
+                    // the largest difference seen on llvmpipe is 3.6e-7
+                    let diff = max_abs_diff(&gpu_field, &cpu.field);
+                    assert!(
+                        diff < 1e-6,
+                        "{} stencil, {} and {:?} differ by {}",
+                        stencil.name(),
+                        integrator,
+                        boundary,
+                        diff
+                    );
+                }
+            }
+        }
+    }
+
+    // a wall island in the middle, the periodic boundary wraps around it
+    fn walls(width: u32, height: u32) -> Mask {
+        let mut mask = Mask::empty(width, height);
+        for y in 4..12 {
+            for x in 10..13 {
+                mask.data[(y * width + x) as usize] = 1.0;
+            }
+        }
+        mask
+    }
+
+    // feed/kill gradient, the left half has its own du/dv, the right half the global ones
+    fn param_map(width: u32, height: u32) -> ParamMap {
+        let mut map = ParamMap::linear_gradient(width, height, (0.02, 0.06), (0.05, 0.065));
+        for (i, pixel) in map.data.chunks_exact_mut(4).enumerate() {
+            if (i as u32 % width) < width / 2 {
+                pixel[2] = 0.15;
+                pixel[3] = 0.05;
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn gpu_matches_cpu_with_walls_and_a_param_map() {
+        if !has_adapter() {
+            eprintln!("no adapter found, skipping the GPU comparison");
+            return;
+        }
+        let (width, height) = (24, 16);
+        let mut headless = pollster::block_on(Headless::new(width, height)).unwrap();
+        let start = InitialCondition::RandomSpots {
+            count: 6,
+            radius: 3,
+        }
+        .generate(width, height, 1)
+        .unwrap();
+        let simulation = &mut headless.simulation;
+        simulation.set_params(&PARAMS);
+        simulation.set_mask(&walls(width, height)).unwrap();
+        simulation.set_param_map(&param_map(width, height)).unwrap();
+
+        for stencil in Stencil::ALL {
+            simulation.set_stencil(stencil).unwrap();
+            for integrator in [Integrator::Heun, Integrator::Imex { iterations: 8 }] {
+                simulation.set_integrator(integrator).unwrap();
+                for boundary in BOUNDARIES {
+                    simulation.set_boundary(boundary);
+                    simulation.write_field(&start).unwrap();
+                    simulation.step(20);
+                    let gpu_field = simulation.read_field().unwrap();
+
+                    let mut cpu = CpuSimulation::new(width, height, start.clone()).unwrap();
+                    cpu.params = PARAMS;
+                    cpu.boundary = boundary;
+                    cpu.stencil = stencil;
+                    cpu.integrator = integrator;
+                    cpu.mask = Some(walls(width, height));
+                    cpu.param_map = Some(param_map(width, height));
+                    cpu.step(20);
+
+                    let diff = max_abs_diff(&gpu_field, &cpu.field);
+                    assert!(
+                        diff < 1e-6,
+                        "{} stencil, {} and {:?} with walls and a map differ by {}",
+                        stencil.name(),
+                        integrator,
+                        boundary,
+                        diff
+                    );
+                }
+            }
+        }
+    }
+}
@@ -9,6 +9,8 @@ pub struct GpuResource {
     pub queue: Queue,
     pub config: SurfaceConfiguration,
     pub size: PhysicalSize<u32>,
+    // what the surface supports, Fifo is always there
+    pub present_modes: Vec<PresentMode>,
 }
 
 pub struct FrameContext {
@@ -81,6 +83,7 @@ impl GpuResource {
             queue: queue_m,
             config: config_m,
             size,
+            present_modes: surface_m_capab.present_modes,
         })
     }
 
@@ -94,6 +97,20 @@ impl GpuResource {
         }
     }
 
+    pub fn set_present_mode(&mut self, mode: PresentMode) -> Result<(), String> {
+        // the Auto modes fall back by themselves
+        let auto = matches!(mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync);
+        if !auto && !self.present_modes.contains(&mode) {
+            return Err(format!(
+                "Present mode {:?} is not supported here, supported are {:?}",
+                mode, self.present_modes
+            ));
+        }
+        self.config.present_mode = mode;
+        self.surface.configure(&self.device, &self.config);
+        Ok(())
+    }
+
     pub fn begin_frame(&self) -> Result<FrameContext, SurfaceError> {
         let surface_texture = self.surface.get_current_texture()?;
         let view = surface_texture
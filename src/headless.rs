@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use wgpu::*;
+
+use crate::{
+    image_io::RgbaImage, offscreen::OffscreenTarget, simulation::Simulation,
+    stability::DIVERGENCE_CHECK_EVERY,
+};
+
+// format of the offscreen target, the same sRGB encoding a window surface uses
+pub const HEADLESS_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+// device without any window or surface
+// takes the first real adapter and falls back to a software one (lavapipe, WARP, ...)
+pub async fn request_headless_device() -> Result<(Device, Queue), String> {
+    let instance_m = Instance::new(&InstanceDescriptor::default());
+
+    let adapter_m = match instance_m
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })
+        .await
+    {
+        Ok(adapter) => adapter,
+        Err(_) => instance_m
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::LowPower,
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            })
+            .await
+            .map_err(|e| format!("No GPU or software adapter found: {}", e))?,
+    };
+    println!("Headless adapter: {:?}", adapter_m.get_info().name);
+
+    adapter_m
+        .request_device(&wgt::DeviceDescriptor {
+            label: Some("Headless Device"),
+            required_features: Features::empty(),
+            required_limits: Limits::default(),
+            memory_hints: MemoryHints::default(),
+            trace: Trace::Off,
+        })
+        .await
+        .map_err(|e| format!("Failed to create device: {}", e))
+}
+
+// simulation plus an offscreen target of the same size
+pub struct Headless {
+    pub simulation: Simulation,
+    pub target: OffscreenTarget,
+}
+
+impl Headless {
+    pub async fn new(width: u32, height: u32) -> Result<Self, String> {
+        let (device, queue) = request_headless_device().await?;
+        let simulation = Simulation::new(&device, &queue, HEADLESS_FORMAT, width, height)?;
+        let target = OffscreenTarget::new(&device, width, height, HEADLESS_FORMAT);
+
+        Ok(Self { simulation, target })
+    }
+
+    // the offscreen target follows the grid size of the checkpoint
+    pub fn load_checkpoint(&mut self, path: &Path) -> Result<(), String> {
+        self.simulation.load_checkpoint(path)?;
+        self.target = OffscreenTarget::new(
+            &self.simulation.device,
+            self.simulation.width(),
+            self.simulation.height(),
+            HEADLESS_FORMAT,
+        );
+        Ok(())
+    }
+
+    // with the divergence check on it stops at the first chunk that diverged
+    pub fn run(&mut self, steps: u32) -> Result<(), String> {
+        if !self.simulation.detect_divergence() {
+            self.simulation.step(steps);
+            return Ok(());
+        }
+
+        let mut remaining = steps;
+        while remaining > 0 {
+            let chunk = remaining.min(DIVERGENCE_CHECK_EVERY);
+            self.simulation.step(chunk);
+            remaining -= chunk;
+            if let Some(divergence) = self.simulation.check_divergence()? {
+                return Err(divergence.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // draws the current field with the display shader and reads it back
+    pub fn render_frame(&self) -> Result<RgbaImage, String> {
+        self.simulation.render(&self.target.view);
+        self.target
+            .read(&self.simulation.device, &self.simulation.queue)
+    }
+}
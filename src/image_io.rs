@@ -0,0 +1,157 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+// 8 bit RGBA image, rows from top to bottom
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    // nearest neighbour lookup as if the image was scaled to target_w x target_h
+    pub fn sample_scaled(&self, x: u32, y: u32, target_w: u32, target_h: u32) -> [u8; 4] {
+        let src_x = ((x as u64 * self.width as u64) / target_w.max(1) as u64) as u32;
+        let src_y = ((y as u64 * self.height as u64) / target_h.max(1) as u64) as u32;
+        let src_x = src_x.min(self.width - 1);
+        let src_y = src_y.min(self.height - 1);
+
+        let idx = ((src_y * self.width + src_x) * 4) as usize;
+        [
+            self.pixels[idx],
+            self.pixels[idx + 1],
+            self.pixels[idx + 2],
+            self.pixels[idx + 3],
+        ]
+    }
+}
+
+// perceived brightness in [0, 1] (Rec. 709 weights), alpha darkens
+pub fn luminance(pixel: [u8; 4]) -> f32 {
+    let rgb = 0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+    rgb / 255.0 * (pixel[3] as f32 / 255.0)
+}
+
+// reads any PNG and converts it to 8 bit RGBA
+pub fn load_png(path: &Path) -> Result<RgbaImage, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+
+    let mut decoder = Decoder::new(BufReader::new(file));
+    // palettes are expanded and 16 bit is stripped to 8 bit
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Failed to decode {:?}: {}", path, e))?;
+
+    let buffer_size = reader
+        .output_buffer_size()
+        .ok_or_else(|| format!("Image {:?} is too large", path))?;
+    let mut buffer = vec![0_u8; buffer_size];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| format!("Failed to decode {:?}: {}", path, e))?;
+    let buffer = &buffer[..info.buffer_size()];
+
+    if info.width == 0 || info.height == 0 {
+        return Err(format!("Image {:?} is empty", path));
+    }
+
+    let pixels = match info.color_type {
+        ColorType::Rgba => buffer.to_vec(),
+        ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|c| [c[0], c[1], c[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|c| [c[0], c[0], c[0], c[1]])
+            .collect(),
+        ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        ColorType::Indexed => {
+            return Err(format!("Palette of {:?} could not be expanded", path));
+        }
+    };
+
+    Ok(RgbaImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+// text chunks (key, value) stored in the PNG, e.g. the simulation parameters
+pub type PngText = Vec<(String, String)>;
+
+// writes 8 bit RGBA
+pub fn save_png(path: &Path, image: &RgbaImage, text: &PngText) -> Result<(), String> {
+    write_png(
+        path,
+        image.width,
+        image.height,
+        ColorType::Rgba,
+        BitDepth::Eight,
+        &image.pixels,
+        text,
+    )
+}
+
+// writes the raw U/V field (RGBA f32 texture layout) as 16 bit RGB with R = U and G = V
+// rows are flipped so the image is upright like in the window
+pub fn save_field_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    field: &[f32],
+    text: &PngText,
+) -> Result<(), String> {
+    let mut data = Vec::with_capacity((width * height * 6) as usize);
+    for row in field.chunks_exact((width * 4) as usize).rev() {
+        for pixel in row.chunks_exact(4) {
+            for value in [pixel[0], pixel[1], 0.0] {
+                let value = (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+                data.extend_from_slice(&value.to_be_bytes()); // PNG is big endian
+            }
+        }
+    }
+
+    write_png(
+        path,
+        width,
+        height,
+        ColorType::Rgb,
+        BitDepth::Sixteen,
+        &data,
+        text,
+    )
+}
+
+fn write_png(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color: ColorType,
+    depth: BitDepth,
+    data: &[u8],
+    text: &PngText,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    let write_error = |e: png::EncodingError| format!("Failed to write {:?}: {}", path, e);
+
+    let mut encoder = Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    for (key, value) in text {
+        encoder
+            .add_text_chunk(key.clone(), value.clone())
+            .map_err(write_error)?;
+    }
+
+    let mut writer = encoder.write_header().map_err(write_error)?;
+    writer.write_image_data(data).map_err(write_error)?;
+    writer.finish().map_err(write_error)
+}
@@ -0,0 +1,383 @@
+// Initial states for the field (RGBA32Float layout, R = U, G = V, B = 0, A = 1)
+// everything starts as U = 1, V = 0 and the generators add V on top
+// all randomness comes from the seed so the same seed gives the same state
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::{font, image_io};
+
+// far beyond any grid, the square of it still fits into an i64
+const MAX_RADIUS: u32 = i32::MAX as u32;
+
+// in scene files the variant is `type` with the names below and the fields next to it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum InitialCondition {
+    // a disc of V in the center (the original start)
+    #[serde(rename = "blob")]
+    CenterBlob { radius: u32 },
+    // V uniformly random in [0, amount] everywhere
+    #[serde(rename = "noise")]
+    UniformNoise { amount: f32 },
+    // discs of V at random places
+    #[serde(rename = "spots")]
+    RandomSpots { count: u32, radius: u32 },
+    // squares of V at random places
+    #[serde(rename = "squares")]
+    Squares { count: u32, size: u32 },
+    // vertical stripes of V, `width` pixels every `period` pixels
+    #[serde(rename = "stripes")]
+    Stripes { period: u32, width: u32 },
+    // a ring of V around the center
+    #[serde(rename = "ring")]
+    Ring { radius: u32, thickness: u32 },
+    // V in every other cell
+    #[serde(rename = "checkerboard")]
+    Checkerboard { cell: u32 },
+    // Pearson 1993: a size x size square with U = 0.5, V = 0.25 in the center
+    // and everything perturbed with +-noise
+    #[serde(rename = "pearson")]
+    PearsonSquare { size: u32, noise: f32 },
+    // grayscale of a PNG scaled to the grid, luminance goes into V
+    // with map_u U becomes 1 - luminance, otherwise U stays 1
+    #[serde(rename = "image")]
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        map_u: bool,
+    },
+    // text in the bundled bitmap font, scaled to fit the grid
+    #[serde(rename = "text")]
+    Text {
+        text: String,
+        #[serde(default)]
+        map_u: bool,
+    },
+}
+
+impl Default for InitialCondition {
+    fn default() -> Self {
+        Self::CenterBlob { radius: 10 }
+    }
+}
+
+impl InitialCondition {
+    // names accepted by from_name, in cycling order
+    pub const NAMES: [&'static str; 8] = [
+        "blob",
+        "noise",
+        "spots",
+        "squares",
+        "stripes",
+        "ring",
+        "checkerboard",
+        "pearson",
+    ];
+
+    // a name from NAMES, "image:<path.png>" or "text:<some text>"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(path) = spec.strip_prefix("image:") {
+            return Ok(Self::Image {
+                path: PathBuf::from(path),
+                map_u: false,
+            });
+        }
+        if let Some(text) = spec.strip_prefix("text:") {
+            return Ok(Self::Text {
+                text: text.replace("\\n", "\n"),
+                map_u: false,
+            });
+        }
+
+        Self::from_name(spec).ok_or_else(|| {
+            format!(
+                "Unknown initial condition '{}', expected one of {}, image:<path> or text:<text>",
+                spec,
+                Self::NAMES.join(", ")
+            )
+        })
+    }
+
+    // built-in generators with their default settings
+    pub fn from_name(name: &str) -> Option<Self> {
+        let init = match name {
+            "blob" => Self::default(),
+            "noise" => Self::UniformNoise { amount: 0.5 },
+            "spots" => Self::RandomSpots {
+                count: 20,
+                radius: 8,
+            },
+            "squares" => Self::Squares {
+                count: 20,
+                size: 16,
+            },
+            "stripes" => Self::Stripes {
+                period: 64,
+                width: 4,
+            },
+            "ring" => Self::Ring {
+                radius: 100,
+                thickness: 4,
+            },
+            "checkerboard" => Self::Checkerboard { cell: 64 },
+            "pearson" => Self::PearsonSquare {
+                size: 20,
+                noise: 0.01,
+            },
+            _ => return None,
+        };
+        Some(init)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CenterBlob { .. } => "blob",
+            Self::UniformNoise { .. } => "noise",
+            Self::RandomSpots { .. } => "spots",
+            Self::Squares { .. } => "squares",
+            Self::Stripes { .. } => "stripes",
+            Self::Ring { .. } => "ring",
+            Self::Checkerboard { .. } => "checkerboard",
+            Self::PearsonSquare { .. } => "pearson",
+            Self::Image { .. } => "image",
+            Self::Text { .. } => "text",
+        }
+    }
+
+    // more spots or squares than pixels only takes forever, sizes larger than the grid are
+    // fine (the shapes are clipped) so scenes saved from a larger grid still load
+    pub fn validate(&self, width: u32, height: u32) -> Result<(), String> {
+        let pixels = width.saturating_mul(height);
+        match self {
+            Self::RandomSpots { count, .. } | Self::Squares { count, .. } if *count > pixels => {
+                Err(format!(
+                    "count must be at most {} for a {}x{} grid, got {}",
+                    pixels, width, height, count
+                ))
+            }
+            Self::UniformNoise { amount: value } | Self::PearsonSquare { noise: value, .. }
+                if !(0.0..=1.0).contains(value) =>
+            {
+                let key = match self {
+                    Self::UniformNoise { .. } => "amount",
+                    _ => "noise",
+                };
+                Err(format!("{} must be between 0 and 1, got {}", key, value))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // only Image can fail (missing or broken file)
+    pub fn generate(&self, width: u32, height: u32, seed: u64) -> Result<Vec<f32>, String> {
+        let mut field = Field::new(width, height);
+        let mut rng = SeededRng::new(seed);
+        let center = (width as i32 / 2, height as i32 / 2);
+
+        match self {
+            Self::CenterBlob { radius } => field.disc(center, *radius),
+            Self::UniformNoise { amount } => {
+                for idx in 0..field.pixels() {
+                    field.set(idx, 1.0, rng.next_f32() * *amount);
+                }
+            }
+            Self::RandomSpots { count, radius } => {
+                for _ in 0..*count {
+                    let spot = rng.next_point(width, height);
+                    field.disc(spot, *radius);
+                }
+            }
+            Self::Squares { count, size } => {
+                for _ in 0..*count {
+                    let corner = rng.next_point(width, height);
+                    field.rect(corner, *size, *size, 1.0, 1.0);
+                }
+            }
+            Self::Stripes {
+                period,
+                width: stripe,
+            } => {
+                let period = (*period).max(1);
+                for x in (0..width).step_by(period as usize) {
+                    field.rect((x as i32, 0), *stripe, height, 1.0, 1.0);
+                }
+            }
+            Self::Ring { radius, thickness } => {
+                let inner = radius.saturating_sub(*thickness / 2).min(MAX_RADIUS) as i64;
+                let outer = radius.saturating_add(thickness.div_ceil(2)).min(MAX_RADIUS) as i64;
+                field.paint_in_radius(center, outer as u32, |dist_sq| {
+                    dist_sq >= inner * inner && dist_sq < outer * outer
+                });
+            }
+            Self::Checkerboard { cell } => {
+                let cell = (*cell).max(1);
+                for y in 0..height {
+                    for x in 0..width {
+                        if (x / cell + y / cell) % 2 == 1 {
+                            let idx = (y * width + x) as usize;
+                            field.set(idx, 1.0, 1.0);
+                        }
+                    }
+                }
+            }
+            Self::PearsonSquare { size, noise } => {
+                let corner = (center.0 - (*size / 2) as i32, center.1 - (*size / 2) as i32);
+                field.rect(corner, *size, *size, 0.5, 0.25);
+                for idx in 0..field.pixels() {
+                    let (u, v) = field.get(idx);
+                    let u = u + (rng.next_f32() * 2.0 - 1.0) * *noise;
+                    let v = v + (rng.next_f32() * 2.0 - 1.0) * *noise;
+                    field.set(idx, u.clamp(0.0, 1.0), v.clamp(0.0, 1.0));
+                }
+            }
+            Self::Image { path, map_u } => {
+                let image = image_io::load_png(path)?;
+                for y in 0..height {
+                    for x in 0..width {
+                        // texture row 0 is drawn at the bottom of the window
+                        let pixel = image.sample_scaled(x, height - 1 - y, width, height);
+                        field.set_luminance(x, y, image_io::luminance(pixel), *map_u);
+                    }
+                }
+            }
+            Self::Text { text, map_u } => {
+                let bitmap = font::rasterize(text);
+                // 80% of the grid, keeping the aspect ratio of the glyphs
+                let scale = (width as f32 * 0.8 / bitmap.width as f32)
+                    .min(height as f32 * 0.8 / bitmap.height as f32);
+                let offset_x = (width as f32 - bitmap.width as f32 * scale) / 2.0;
+                let offset_y = (height as f32 - bitmap.height as f32 * scale) / 2.0;
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let window_y = (height - 1 - y) as f32;
+                        let bx = ((x as f32 - offset_x) / scale).floor();
+                        let by = ((window_y - offset_y) / scale).floor();
+                        if bx < 0.0 || by < 0.0 {
+                            continue;
+                        }
+                        let (bx, by) = (bx as u32, by as u32);
+                        if bx < bitmap.width
+                            && by < bitmap.height
+                            && bitmap.pixels[(by * bitmap.width + bx) as usize]
+                        {
+                            field.set_luminance(x, y, 1.0, *map_u);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(field.data)
+    }
+}
+
+// RGBA field with a few drawing helpers
+struct Field {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Field {
+    // U everywhere, no V
+    fn new(width: u32, height: u32) -> Self {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            data.extend_from_slice(&[1.0, 0.0, 0.0, 1.0]);
+        }
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn pixels(&self) -> usize {
+        (self.width * self.height) as usize
+    }
+
+    fn get(&self, idx: usize) -> (f32, f32) {
+        (self.data[idx * 4], self.data[idx * 4 + 1])
+    }
+
+    fn set(&mut self, idx: usize, u: f32, v: f32) {
+        self.data[idx * 4] = u;
+        self.data[idx * 4 + 1] = v;
+    }
+
+    // V = luminance, U = 1 - luminance if map_u
+    fn set_luminance(&mut self, x: u32, y: u32, luminance: f32, map_u: bool) {
+        let idx = (y * self.width + x) as usize;
+        let u = if map_u { 1.0 - luminance } else { 1.0 };
+        self.set(idx, u, luminance);
+    }
+
+    // pixels with distance^2 < radius^2 get V
+    fn disc(&mut self, center: (i32, i32), radius: u32) {
+        let radius = radius.min(MAX_RADIUS);
+        let r_sq = radius as i64 * radius as i64;
+        self.paint_in_radius(center, radius, |dist_sq| dist_sq < r_sq);
+    }
+
+    // sets V = 1 on pixels around center where keep(distance^2) is true
+    // in i64, a radius above i32::MAX from the API must not wrap
+    fn paint_in_radius(&mut self, center: (i32, i32), radius: u32, keep: impl Fn(i64) -> bool) {
+        let (cx, cy, r) = (center.0 as i64, center.1 as i64, radius as i64);
+        for y in (cy - r).max(0)..(cy + r + 1).min(self.height as i64) {
+            for x in (cx - r).max(0)..(cx + r + 1).min(self.width as i64) {
+                let dist_x = x - cx;
+                let dist_y = y - cy;
+                if keep(dist_x * dist_x + dist_y * dist_y) {
+                    let idx = (y as u32 * self.width + x as u32) as usize;
+                    self.set(idx, 1.0, 1.0);
+                }
+            }
+        }
+    }
+
+    // clipped to the grid, in i64 like paint_in_radius
+    fn rect(&mut self, corner: (i32, i32), w: u32, h: u32, u: f32, v: f32) {
+        let x0 = (corner.0 as i64).max(0);
+        let y0 = (corner.1 as i64).max(0);
+        let x1 = (corner.0 as i64 + w as i64).min(self.width as i64);
+        let y1 = (corner.1 as i64 + h as i64).min(self.height as i64);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let idx = (y as u32 * self.width + x as u32) as usize;
+                self.set(idx, u, v);
+            }
+        }
+    }
+}
+
+// SplitMix64, small and the same on every platform and every version of this crate
+struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1_u64 << 24) as f32
+    }
+
+    fn next_point(&mut self, width: u32, height: u32) -> (i32, i32) {
+        let x = self.next_u64() % width as u64;
+        let y = self.next_u64() % height as u64;
+        (x as i32, y as i32)
+    }
+}
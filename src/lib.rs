@@ -0,0 +1,30 @@
+// Reaction diffusion simulation on the GPU
+// the window app in main.rs is only one front-end of it
+
+pub mod brush;
+pub mod checkpoint;
+pub mod clock;
+pub mod cpu_reference;
+mod font;
+pub mod headless;
+pub mod image_io;
+pub mod initial_conditions;
+pub mod mask;
+pub mod offscreen;
+pub mod param_map;
+pub mod preprocessor;
+pub mod presets;
+pub mod rd_system;
+pub mod readback;
+pub mod recorder;
+pub mod scene;
+pub mod shader_check;
+pub mod shader_watcher;
+pub mod simulation;
+pub mod stability;
+
+pub use rd_system::{
+    Boundary, DEFAULT_HEIGHT, DEFAULT_WIDTH, Integrator, Palette, ReactionDiffusionSystem,
+    SimParams, Stencil,
+};
+pub use simulation::Simulation;
@@ -1,29 +1,183 @@
 use winit::{
     application::ApplicationHandler,
     dpi::LogicalSize,
-    event::WindowEvent,
+    event::{ElementState, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
 
-use crate::state::State;
+use std::path::Path;
 
+use reaction_diffusion_wgpu::{
+    cpu_reference::{self, CpuSimulation},
+    headless::Headless,
+    image_io,
+    recorder::{Recorder, RecorderConfig},
+    scene::Scene,
+};
+
+use crate::{cli::Options, state::State};
+
+mod cli;
 mod gpu_resources;
-mod rd_system;
 mod state;
-mod shader_watcher;
+mod ui;
 
 fn main() {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    if options.headless {
+        if let Err(e) = pollster::block_on(run_headless(&options)) {
+            eprintln!("Headless run failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop_m = EventLoop::new().expect("Failed to create Event Loop!");
     event_loop_m.set_control_flow(ControlFlow::Wait);
 
-    let mut app = App::default();
+    let mut app = App {
+        options,
+        window: None,
+        state: None,
+    };
     let _ = event_loop_m.run_app(&mut app);
 }
 
+// no window: step N times and export the last frame
+async fn run_headless(options: &Options) -> Result<(), String> {
+    let mut headless = Headless::new(options.width, options.height).await?;
+    headless.simulation.reset(&options.init, options.seed)?;
+    // stencil and integrator before the params, the stable dt depends on them
+    headless.simulation.set_stencil(options.stencil)?;
+    headless.simulation.set_integrator(options.integrator)?;
+    headless.simulation.set_auto_dt(options.stability.auto_dt);
+    headless.simulation.set_params(&options.params);
+    headless
+        .simulation
+        .set_detect_divergence(options.stability.detect_divergence)?;
+    headless.simulation.set_palette(options.palette);
+    headless.simulation.set_boundary(options.boundary);
+    if let Some(param_map) = &options.param_map {
+        let param_map = param_map.generate(options.width, options.height)?;
+        headless.simulation.set_param_map(&param_map)?;
+    }
+    if let Some(mask) = &options.mask {
+        let mask = mask.generate(options.width, options.height)?;
+        headless.simulation.set_mask(&mask)?;
+    }
+    headless
+        .simulation
+        .set_workgroup_size(options.workgroup_size)?;
+    if let Some(path) = &options.checkpoint {
+        headless.load_checkpoint(path)?;
+        println!(
+            "Resuming {:?} at step {}",
+            path, headless.simulation.step_count
+        );
+    }
+
+    // the CPU reference starts from the same field
+    let start_field = match options.validate {
+        true => Some(headless.simulation.read_field()?),
+        false => None,
+    };
+
+    println!("Running {} steps headless", options.steps);
+    match &options.record {
+        Some(dir) => record_headless(&mut headless, options, dir)?,
+        None => headless.run(options.steps)?,
+    }
+
+    let frame = headless.render_frame()?;
+    image_io::save_png(&options.output, &frame, &headless.simulation.png_text())?;
+    println!("Frame written to {:?}", options.output);
+
+    if let Some(path) = &options.save_checkpoint {
+        headless.simulation.save_checkpoint(path)?;
+        println!("Checkpoint written to {:?}", path);
+    }
+
+    if let Some(path) = &options.save_scene {
+        let mut scene = Scene::from_simulation(&headless.simulation, &options.init, options.seed);
+        scene.display.present_mode = options.present_mode;
+        scene.display.steps_per_frame = options.steps_per_frame;
+        scene.brush = options.brush;
+        scene.mask = options.mask.clone();
+        scene.param_map = options.param_map.clone();
+        scene.save(path)?;
+        println!("Scene written to {:?}", path);
+    }
+
+    if let Some(start_field) = start_field {
+        validate_against_cpu(&headless, start_field, options)?;
+    }
+    Ok(())
+}
+
+// steps in chunks of record_every and captures a frame after each chunk (and the start)
+fn record_headless(headless: &mut Headless, options: &Options, dir: &Path) -> Result<(), String> {
+    let mut recorder = Recorder::new(RecorderConfig::in_dir(dir, 1))?;
+    recorder.add_frame(&headless.render_frame()?, &headless.simulation.png_text())?;
+
+    let mut remaining = options.steps;
+    while remaining > 0 {
+        let steps = remaining.min(options.record_every);
+        headless.run(steps)?;
+        remaining -= steps;
+
+        recorder.add_frame(&headless.render_frame()?, &headless.simulation.png_text())?;
+    }
+
+    let frames = recorder.finish()?;
+    println!("Recorded {} frames to {:?}", frames, dir);
+    Ok(())
+}
+
+// runs the same steps on the CPU reference and compares U and V
+fn validate_against_cpu(
+    headless: &Headless,
+    start_field: Vec<f32>,
+    options: &Options,
+) -> Result<(), String> {
+    let simulation = &headless.simulation;
+    let gpu_field = simulation.read_field()?;
+
+    let mut cpu = CpuSimulation::new(simulation.width(), simulation.height(), start_field)?;
+    cpu.params = *simulation.params();
+    cpu.boundary = simulation.boundary();
+    cpu.stencil = simulation.stencil();
+    cpu.integrator = simulation.integrator();
+    cpu.param_map = simulation.read_param_map()?;
+    cpu.mask = Some(simulation.read_mask()?);
+    println!("Running {} steps on the CPU reference", options.steps);
+    cpu.step(options.steps);
+
+    let diff = cpu_reference::max_abs_diff(&gpu_field, &cpu.field);
+    println!("Largest U/V difference GPU vs CPU: {}", diff);
+    if diff > options.tolerance {
+        return Err(format!(
+            "GPU result differs from the CPU reference by {} (tolerance {})",
+            diff, options.tolerance
+        ));
+    }
+    Ok(())
+}
+
 // making the Application
-#[derive(Default)]
 struct App {
+    options: Options,
     window: Option<&'static Window>,
     state: Option<State>,
 }
@@ -42,7 +196,8 @@ impl ApplicationHandler for App {
         let window: &'static Window = Box::leak(Box::new(window));
 
         // create GPU state
-        let state = pollster::block_on(State::new(window)).expect("wgpu init failed!");
+        let state =
+            pollster::block_on(State::new(window, &self.options)).expect("wgpu init failed!");
         self.window = Some(window);
         self.state = Some(state);
     }
@@ -53,6 +208,9 @@ impl ApplicationHandler for App {
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
+        // the panel gets every event first
+        let ui_consumed = self.state.as_mut().is_some_and(|st| st.ui_event(&event));
+
         match event {
             WindowEvent::CloseRequested => {
                 println!("Closing Window Requested!");
@@ -65,6 +223,33 @@ impl ApplicationHandler for App {
                 }
             }
 
+            WindowEvent::KeyboardInput { event, .. }
+                if event.state == ElementState::Pressed && !event.repeat && !ui_consumed =>
+            {
+                if let Some(st) = &mut self.state {
+                    st.key_pressed(&event.logical_key);
+                }
+            }
+
+            // a release always ends the stroke, even over the panel
+            WindowEvent::MouseInput { state, button, .. }
+                if !ui_consumed || state == ElementState::Released =>
+            {
+                if let Some(st) = &mut self.state {
+                    st.mouse_input(button, state);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(st) = &mut self.state {
+                    st.cursor_moved(position);
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                if let Some(st) = &mut self.state {
+                    st.cursor_left();
+                }
+            }
+
             WindowEvent::RedrawRequested => {
                 if let Some(st) = &mut self.state {
                     let _ = st.render();
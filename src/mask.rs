@@ -0,0 +1,140 @@
+// walls inside the grid, uploaded to the mask texture (R32Float)
+// 1 = wall, 0 = the reaction runs there
+// a wall is what the boundary condition says for the cells next to it: no flux for
+// neumann and periodic, the fixed U and V for dirichlet. nothing grows inside a wall
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::initial_conditions::InitialCondition;
+
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+// where the walls come from, in scene files the variant is `type`
+// the shape is the inside, everything around it is a wall (or the other way with invert)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MaskShape {
+    // the largest circle that fits into the grid
+    Circle {
+        #[serde(default)]
+        invert: bool,
+    },
+    // bright pixels of a PNG (scaled to the grid), dark ones are walls
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        invert: bool,
+    },
+    // text in the bundled bitmap font, like the text initial condition
+    Text {
+        text: String,
+        #[serde(default)]
+        invert: bool,
+    },
+}
+
+impl MaskShape {
+    // "circle", "image:<path.png>" or "text:<some text>"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(path) = spec.strip_prefix("image:") {
+            return Ok(Self::Image {
+                path: PathBuf::from(path),
+                invert: false,
+            });
+        }
+        if let Some(text) = spec.strip_prefix("text:") {
+            return Ok(Self::Text {
+                text: text.replace("\\n", "\n"),
+                invert: false,
+            });
+        }
+        match spec {
+            "circle" => Ok(Self::Circle { invert: false }),
+            _ => Err(format!(
+                "Unknown mask '{}', expected circle, image:<path> or text:<text>",
+                spec
+            )),
+        }
+    }
+
+    // walls inside and the shape open
+    pub fn set_invert(&mut self, value: bool) {
+        match self {
+            Self::Circle { invert } | Self::Image { invert, .. } | Self::Text { invert, .. } => {
+                *invert = value
+            }
+        }
+    }
+
+    // only Image can fail (missing or broken file)
+    pub fn generate(&self, width: u32, height: u32) -> Result<Mask, String> {
+        let (inside, invert) = match self {
+            Self::Circle { invert } => {
+                let radius = width.min(height) as f32 / 2.0;
+                let center = (width as f32 / 2.0, height as f32 / 2.0);
+                let inside = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let dx = x as f32 + 0.5 - center.0;
+                        let dy = y as f32 + 0.5 - center.1;
+                        dx * dx + dy * dy <= radius * radius
+                    })
+                    .collect();
+                (inside, *invert)
+            }
+            // the initial conditions already scale images and draw text, V is the shape
+            Self::Image { path, invert } => {
+                let init = InitialCondition::Image {
+                    path: path.clone(),
+                    map_u: false,
+                };
+                (v_above_half(&init, width, height)?, *invert)
+            }
+            Self::Text { text, invert } => {
+                let init = InitialCondition::Text {
+                    text: text.clone(),
+                    map_u: false,
+                };
+                (v_above_half(&init, width, height)?, *invert)
+            }
+        };
+
+        Ok(Mask {
+            width,
+            height,
+            data: inside
+                .into_iter()
+                .map(|inside: bool| if inside != invert { 0.0 } else { 1.0 })
+                .collect(),
+        })
+    }
+}
+
+impl Mask {
+    // no walls
+    pub fn empty(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0.0; (width * height) as usize],
+        }
+    }
+
+    pub fn is_wall(&self, x: u32, y: u32) -> bool {
+        self.data[(y * self.width + x) as usize] > 0.5
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|&wall| wall <= 0.5)
+    }
+}
+
+fn v_above_half(init: &InitialCondition, width: u32, height: u32) -> Result<Vec<bool>, String> {
+    let field = init.generate(width, height, 0)?;
+    Ok(field.chunks_exact(4).map(|pixel| pixel[1] >= 0.5).collect())
+}
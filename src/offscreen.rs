@@ -0,0 +1,53 @@
+use wgpu::*;
+
+use crate::{image_io::RgbaImage, readback};
+
+// color texture that replaces the swapchain when there is no window
+// (headless runs, screenshots)
+pub struct OffscreenTarget {
+    pub texture: Texture,
+    pub view: TextureView,
+}
+
+impl OffscreenTarget {
+    pub fn new(device: &Device, width: u32, height: u32, format: TextureFormat) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        Self { texture, view }
+    }
+
+    // always returns RGBA, surfaces are often BGRA
+    pub fn read(&self, device: &Device, queue: &Queue) -> Result<RgbaImage, String> {
+        let mut pixels = readback::read_texture(device, queue, &self.texture)?;
+
+        match self.texture.format() {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => {}
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+                for pixel in pixels.chunks_exact_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            format => return Err(format!("Can not export {:?} as RGBA", format)),
+        }
+
+        Ok(RgbaImage {
+            width: self.texture.width(),
+            height: self.texture.height(),
+            pixels,
+        })
+    }
+}
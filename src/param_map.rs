@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{image_io, rd_system::SimParams};
+
+// the part of Pearson's parameter space with all the known patterns
+pub const DEFAULT_FEED_RANGE: [f32; 2] = [0.01, 0.1];
+pub const DEFAULT_KILL_RANGE: [f32; 2] = [0.045, 0.07];
+
+// du/dv value in a parameter map for "take the global du/dv"
+pub const GLOBAL_DIFFUSION: f32 = -1.0;
+
+// per pixel parameters, uploaded to the parameter texture (RGBA32Float)
+// R = feed, G = kill, B = du, A = dv. du or dv below 0 (GLOBAL_DIFFUSION) means the
+// pixel uses the global one, so changing du/dv in SimParams still works with a map
+pub struct ParamMap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+// where the parameter map comes from, in scene files the variant is `type`
+// feed and kill are the ranges the map goes through, du and dv stay the global ones
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ParamMapSource {
+    // feed from bottom to top, kill from left to right
+    Gradient {
+        #[serde(default = "default_feed_range")]
+        feed: [f32; 2],
+        #[serde(default = "default_kill_range")]
+        kill: [f32; 2],
+    },
+    // red channel of a PNG (scaled to the grid) is feed, green is kill
+    Image {
+        path: PathBuf,
+        #[serde(default = "default_feed_range")]
+        feed: [f32; 2],
+        #[serde(default = "default_kill_range")]
+        kill: [f32; 2],
+    },
+}
+
+fn default_feed_range() -> [f32; 2] {
+    DEFAULT_FEED_RANGE
+}
+
+fn default_kill_range() -> [f32; 2] {
+    DEFAULT_KILL_RANGE
+}
+
+impl ParamMapSource {
+    // "gradient" or "image:<path.png>", both with the default ranges
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(path) = spec.strip_prefix("image:") {
+            return Ok(Self::Image {
+                path: PathBuf::from(path),
+                feed: DEFAULT_FEED_RANGE,
+                kill: DEFAULT_KILL_RANGE,
+            });
+        }
+        match spec {
+            "gradient" => Ok(Self::Gradient {
+                feed: DEFAULT_FEED_RANGE,
+                kill: DEFAULT_KILL_RANGE,
+            }),
+            _ => Err(format!(
+                "Unknown parameter map '{}', expected gradient or image:<path>",
+                spec
+            )),
+        }
+    }
+
+    // "<feed0>,<feed1>:<kill0>,<kill1>"
+    pub fn parse_ranges(spec: &str) -> Result<([f32; 2], [f32; 2]), String> {
+        let error = || {
+            format!(
+                "Invalid parameter ranges '{}', expected <feed0>,<feed1>:<kill0>,<kill1>",
+                spec
+            )
+        };
+        let range = |text: &str| -> Result<[f32; 2], String> {
+            let (a, b) = text.split_once(',').ok_or_else(error)?;
+            Ok([
+                a.trim().parse().map_err(|_| error())?,
+                b.trim().parse().map_err(|_| error())?,
+            ])
+        };
+        let (feed, kill) = spec.split_once(':').ok_or_else(error)?;
+        Ok((range(feed)?, range(kill)?))
+    }
+
+    pub fn set_ranges(&mut self, feed_range: [f32; 2], kill_range: [f32; 2]) {
+        match self {
+            Self::Gradient { feed, kill } | Self::Image { feed, kill, .. } => {
+                *feed = feed_range;
+                *kill = kill_range;
+            }
+        }
+    }
+
+    // ranges have to be numbers >= 0, the errors name the key
+    pub fn validate(&self) -> Result<(), String> {
+        let (Self::Gradient { feed, kill } | Self::Image { feed, kill, .. }) = self;
+        for (key, range) in [("feed", feed), ("kill", kill)] {
+            if range.iter().any(|value| !value.is_finite() || *value < 0.0) {
+                return Err(format!("{} must be two numbers >= 0, got {:?}", key, range));
+            }
+        }
+        Ok(())
+    }
+
+    // only Image can fail (missing or broken file)
+    pub fn generate(&self, width: u32, height: u32) -> Result<ParamMap, String> {
+        match self {
+            Self::Gradient { feed, kill } => Ok(ParamMap::linear_gradient(
+                width,
+                height,
+                (feed[0], feed[1]),
+                (kill[0], kill[1]),
+            )),
+            Self::Image { path, feed, kill } => {
+                ParamMap::from_png(path, width, height, (feed[0], feed[1]), (kill[0], kill[1]))
+            }
+        }
+    }
+}
+
+impl ParamMap {
+    // the largest du or dv the map sets itself and whether any pixel uses the global ones
+    pub fn diffusion(&self) -> (f32, bool) {
+        self.data
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[3]])
+            .fold((0.0, false), |(max, global), d| match d < 0.0 {
+                true => (max, true),
+                false => (max.max(d), global),
+            })
+    }
+
+    // the same parameters everywhere
+    pub fn uniform(width: u32, height: u32, params: &SimParams) -> Self {
+        Self::from_fn(width, height, |_, _| {
+            [params.feed, params.kill, params.du, params.dv]
+        })
+    }
+
+    // Pearson style parameter space map
+    // kill goes from left to right, feed goes from bottom to top of the window
+    // diffusion rates are the global ones
+    pub fn linear_gradient(width: u32, height: u32, feed: (f32, f32), kill: (f32, f32)) -> Self {
+        Self::from_fn(width, height, |x, y| {
+            // texture row 0 is drawn at the bottom of the window
+            let t_x = x as f32 / (width.max(2) - 1) as f32;
+            let t_y = y as f32 / (height.max(2) - 1) as f32;
+            [
+                lerp(feed.0, feed.1, t_y),
+                lerp(kill.0, kill.1, t_x),
+                GLOBAL_DIFFUSION,
+                GLOBAL_DIFFUSION,
+            ]
+        })
+    }
+
+    // red channel is mapped to the feed range, green channel to the kill range
+    // the image is scaled to the grid and drawn upright in the window
+    pub fn from_png(
+        path: &Path,
+        width: u32,
+        height: u32,
+        feed: (f32, f32),
+        kill: (f32, f32),
+    ) -> Result<Self, String> {
+        let image = image_io::load_png(path)?;
+        Ok(Self::from_fn(width, height, |x, y| {
+            let pixel = image.sample_scaled(x, height - 1 - y, width, height);
+            [
+                lerp(feed.0, feed.1, pixel[0] as f32 / 255.0),
+                lerp(kill.0, kill.1, pixel[1] as f32 / 255.0),
+                GLOBAL_DIFFUSION,
+                GLOBAL_DIFFUSION,
+            ]
+        }))
+    }
+
+    fn from_fn(width: u32, height: u32, f: impl Fn(u32, u32) -> [f32; 4]) -> Self {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&f(x, y));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -0,0 +1,456 @@
+// a small C like preprocessor for the WGSL shaders
+//
+//   #include "common.wgsl"   pasted in once, relative to the including file
+//   #define NAME value       NAME is replaced by value in the lines after it
+//   #ifdef NAME / #ifndef NAME / #else / #endif
+//
+// defines can also come from Rust when a pipeline is built (see load_ablsolute_path)
+// every output line remembers the file and line it came from, so errors point at the real source
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::shader_check::{self, Label, ShaderError};
+
+pub struct Preprocessed {
+    pub source: String,
+    // every file that was read or tried to read, the root first
+    pub files: Vec<PathBuf>,
+    // (index into files, 1 based line) for every line of source
+    lines: Vec<(usize, u32)>,
+}
+
+// where a byte offset of the expanded source came from
+pub struct SourcePosition<'a> {
+    pub path: &'a Path,
+    // 1 based, in that file
+    pub line: u32,
+    // 1 based, in the expanded line (only differs after a replaced define)
+    pub column: u32,
+    // the expanded line
+    pub text: &'a str,
+}
+
+impl Preprocessed {
+    pub fn position(&self, offset: usize) -> Option<SourcePosition<'_>> {
+        let prefix = self.source.get(..offset.min(self.source.len()))?;
+        let index = prefix.matches('\n').count();
+        let line_start = prefix.rfind('\n').map_or(0, |p| p + 1);
+        let &(file, line) = self.lines.get(index).or(self.lines.last())?;
+
+        Some(SourcePosition {
+            path: &self.files[file],
+            line,
+            column: (prefix.len() - line_start) as u32 + 1,
+            text: self.source[line_start..].lines().next().unwrap_or(""),
+        })
+    }
+}
+
+// expands the file at `path` with the given defines (NAME, value), a flag has an empty value
+pub fn preprocess(path: &Path, defines: &[(&str, String)]) -> Result<Preprocessed, ShaderError> {
+    let mut preprocessor = Preprocessor::new(defines);
+    preprocessor.run(path)?;
+    Ok(preprocessor.finish())
+}
+
+// every file the shader at `path` uses, also when it does not preprocess
+// the watcher needs this to find the pipelines an edited include belongs to
+pub fn included_files(path: &Path, defines: &[(&str, String)]) -> Vec<PathBuf> {
+    let mut preprocessor = Preprocessor::new(defines);
+    let _ = preprocessor.run(path);
+    preprocessor.finish().files
+}
+
+struct Preprocessor {
+    defines: HashMap<String, String>,
+    files: Vec<PathBuf>,
+    // files being expanded right now, to find include cycles
+    stack: Vec<PathBuf>,
+    source: String,
+    lines: Vec<(usize, u32)>,
+}
+
+// an open #ifdef or #ifndef
+struct Condition {
+    active: bool,
+    seen_else: bool,
+    line: u32,
+}
+
+impl Preprocessor {
+    fn new(defines: &[(&str, String)]) -> Self {
+        Self {
+            defines: defines
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+            files: Vec::new(),
+            stack: Vec::new(),
+            source: String::new(),
+            lines: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Preprocessed {
+        Preprocessed {
+            source: self.source,
+            files: self.files,
+            lines: self.lines,
+        }
+    }
+
+    fn run(&mut self, path: &Path) -> Result<(), ShaderError> {
+        let path = fs::canonicalize(path).map_err(|e| {
+            self.files.push(path.to_path_buf());
+            ShaderError::without_location(
+                path.to_path_buf(),
+                format!("Failed to read shader: {}", e),
+            )
+        })?;
+        self.expand(path)
+    }
+
+    fn expand(&mut self, path: PathBuf) -> Result<(), ShaderError> {
+        let file_index = self.files.len();
+        self.files.push(path.clone());
+        let text = fs::read_to_string(&path).map_err(|e| {
+            ShaderError::without_location(path.clone(), format!("Failed to read shader: {}", e))
+        })?;
+        self.stack.push(path.clone());
+
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let number = index as u32 + 1;
+            let active = conditions.iter().all(|c| c.active);
+            let trimmed = line.trim_start();
+            let Some(directive) = trimmed.strip_prefix('#') else {
+                if active {
+                    self.push_line(file_index, number, line);
+                }
+                continue;
+            };
+
+            let column = (line.len() - trimmed.len()) as u32 + 1;
+            let error = |message: String| directive_error(&path, number, column, line, &message);
+            let (name, argument) = split_word(directive);
+            match name {
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains_key(argument);
+                    conditions.push(Condition {
+                        active: defined == (name == "ifdef"),
+                        seen_else: false,
+                        line: number,
+                    });
+                }
+                "else" => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                    if condition.seen_else {
+                        return Err(error("second #else for the same #ifdef".to_string()));
+                    }
+                    condition.active = !condition.active;
+                    condition.seen_else = true;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                // everything below is skipped in an inactive block
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = split_word(argument);
+                    if define.is_empty() {
+                        return Err(error("#define needs a name".to_string()));
+                    }
+                    self.defines.insert(define.to_string(), value.to_string());
+                }
+                "include" => {
+                    let file = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| error("#include needs a file name in quotes".to_string()))?;
+                    let include = path.parent().unwrap_or(Path::new(".")).join(file);
+                    let include = fs::canonicalize(&include).map_err(|e| {
+                        self.files.push(include.clone());
+                        error(format!("Failed to include {:?}: {}", file, e))
+                    })?;
+
+                    if self.stack.contains(&include) {
+                        return Err(error(format!("{:?} includes itself", file)));
+                    }
+                    // like a header with an include guard, a second include adds nothing
+                    if !self.files.contains(&include) {
+                        self.expand(include)?;
+                    }
+                }
+                _ => return Err(error(format!("unknown directive #{}", name))),
+            }
+        }
+
+        if let Some(condition) = conditions.last() {
+            let line = text.lines().nth(condition.line as usize - 1).unwrap_or("");
+            return Err(directive_error(
+                &path,
+                condition.line,
+                1,
+                line,
+                "#ifdef without #endif",
+            ));
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn push_line(&mut self, file_index: usize, number: u32, line: &str) {
+        self.source.push_str(&self.substitute(line));
+        self.source.push('\n');
+        self.lines.push((file_index, number));
+    }
+
+    // replaces whole identifiers that are defined with a value
+    // numbers are words too, so the u of 16u is never replaced
+    fn substitute(&self, line: &str) -> String {
+        let mut out = String::with_capacity(line.len());
+        let mut rest = line;
+        while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            match self.defines.get(word) {
+                Some(value) if !value.is_empty() => out.push_str(value),
+                _ => out.push_str(word),
+            }
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+// "include \"a.wgsl\"" -> ("include", "\"a.wgsl\"")
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn directive_error(path: &Path, line: u32, column: u32, text: &str, message: &str) -> ShaderError {
+    let label = Label {
+        path,
+        line,
+        column,
+        length: text.trim().chars().count() as u32,
+        text,
+        message: "",
+    };
+    ShaderError {
+        path: path.to_path_buf(),
+        line: Some(line),
+        column: Some(column),
+        message: shader_check::render_diagnostic(message, &[label], &[]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory with the given shader files, one per test and process (the tests run in parallel)
+    fn shader_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rd_preprocessor_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            fs::write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    fn expand(dir: &Path, defines: &[(&str, String)]) -> Result<Preprocessed, ShaderError> {
+        preprocess(&dir.join("main.wgsl"), defines)
+    }
+
+    fn expand_error(dir: &Path) -> ShaderError {
+        match expand(dir, &[]) {
+            Ok(preprocessed) => panic!("expanded without an error:\n{}", preprocessed.source),
+            Err(e) => e,
+        }
+    }
+
+    fn file_name(path: &Path) -> &str {
+        path.file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn ifdef_else_endif_nest() {
+        let main = "\
+#define OUTER
+#ifdef OUTER
+a
+  #ifdef INNER
+b
+  #else
+c
+    #ifndef INNER
+d
+    #endif
+  #endif
+#else
+e
+#ifdef INNER
+f
+#else
+#include \"missing.wgsl\"
+#endif
+#endif
+g
+";
+        let dir = shader_dir("ifdef", &[("main.wgsl", main)]);
+        assert_eq!(expand(&dir, &[]).unwrap().source, "a\nc\nd\ng\n");
+        // an inactive block is skipped whole, the missing include is never read
+        let inner = [("INNER", String::new())];
+        assert_eq!(expand(&dir, &inner).unwrap().source, "a\nb\ng\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let dir = shader_dir(
+            "include_once",
+            &[
+                (
+                    "main.wgsl",
+                    "#include \"common.wgsl\"\n#include \"a.wgsl\"\n#include \"common.wgsl\"\nmain\n",
+                ),
+                ("a.wgsl", "#include \"common.wgsl\"\na\n"),
+                ("common.wgsl", "// common\ncommon\n"),
+            ],
+        );
+        let preprocessed = expand(&dir, &[]).unwrap();
+        assert_eq!(preprocessed.source, "// common\ncommon\na\nmain\n");
+        let files: Vec<&str> = preprocessed.files.iter().map(|f| file_name(f)).collect();
+        assert_eq!(files, ["main.wgsl", "common.wgsl", "a.wgsl"]);
+
+        // every line still knows its file and line
+        let offset = preprocessed.source.find("a\n").unwrap();
+        let position = preprocessed.position(offset).unwrap();
+        assert_eq!((file_name(position.path), position.line), ("a.wgsl", 2));
+        let offset = preprocessed.source.find("\ncommon\n").unwrap() + 1;
+        let position = preprocessed.position(offset).unwrap();
+        assert_eq!(
+            (file_name(position.path), position.line),
+            ("common.wgsl", 2)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_an_error() {
+        let dir = shader_dir(
+            "cycle",
+            &[
+                ("main.wgsl", "#include \"a.wgsl\"\n"),
+                ("a.wgsl", "a\n#include \"b.wgsl\"\n"),
+                ("b.wgsl", "b\n\n #include \"a.wgsl\"\n"),
+            ],
+        );
+        let error = expand_error(&dir);
+        assert_eq!(file_name(&error.path), "b.wgsl");
+        assert_eq!((error.line, error.column), (Some(3), Some(2)));
+        assert!(
+            error.message.contains("\"a.wgsl\" includes itself"),
+            "{}",
+            error.message
+        );
+
+        // a file that includes itself directly is a cycle too
+        fs::write(dir.join("main.wgsl"), "#include \"main.wgsl\"\n").unwrap();
+        let error = expand_error(&dir);
+        assert_eq!((file_name(&error.path), error.line), ("main.wgsl", Some(1)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn defines_replace_whole_words() {
+        let main = "\
+let before = N;
+#define N 4
+#define FLAG
+#define u u_v.x
+let a = array<f32, N>(u, u_v.y, N_2, 16u, 0x1u);
+#ifdef FLAG
+let b = FLAG + WG;
+#endif
+";
+        let dir = shader_dir("define", &[("main.wgsl", main)]);
+        let preprocessed = expand(&dir, &[("WG", "8u".to_string())]).unwrap();
+        // the u of 16u and 0x1u belongs to the number, N_2 and u_v are other words
+        // a flag has no value and stays as it is
+        assert_eq!(
+            preprocessed.source,
+            "let before = N;\n\
+             let a = array<f32, 4>(u_v.x, u_v.y, N_2, 16u, 0x1u);\n\
+             let b = FLAG + 8u;\n"
+        );
+
+        // columns are in the expanded line
+        let offset = preprocessed.source.find("N_2").unwrap();
+        let position = preprocessed.position(offset).unwrap();
+        assert_eq!((position.line, position.column), (5, 37));
+        assert_eq!(
+            position.text,
+            "let a = array<f32, 4>(u_v.x, u_v.y, N_2, 16u, 0x1u);"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors_point_at_the_directive() {
+        // (source, line, column, message)
+        let cases = [
+            ("a\n\n   #bogus\n", 3, 4, "unknown directive #bogus"),
+            ("#else\n", 1, 1, "#else without #ifdef"),
+            ("#ifdef A\n#else\n  #else\n#endif\n", 3, 3, "second #else"),
+            ("a\n#endif\n", 2, 1, "#endif without #ifdef"),
+            ("a\n#ifndef A\nb\n", 2, 1, "#ifdef without #endif"),
+            ("#define\n", 1, 1, "#define needs a name"),
+            (
+                "\n#include common.wgsl\n",
+                2,
+                1,
+                "needs a file name in quotes",
+            ),
+            (
+                "\n\n\t#include \"missing.wgsl\"\n",
+                3,
+                2,
+                "Failed to include \"missing.wgsl\"",
+            ),
+        ];
+        for (i, (source, line, column, message)) in cases.into_iter().enumerate() {
+            let dir = shader_dir(&format!("error_{}", i), &[("main.wgsl", source)]);
+            let error = expand_error(&dir);
+            assert_eq!(file_name(&error.path), "main.wgsl", "{:?}", source);
+            assert_eq!(
+                (error.line, error.column),
+                (Some(line), Some(column)),
+                "{:?}",
+                source
+            );
+            assert!(error.message.contains(message), "{}", error.message);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// named Gray-Scott regimes
+// Pearson's classes use his diffusion rates (2e-5 and 1e-5 on a 2.5 x 2.5 domain with
+// 256 x 256 pixels) converted to grid units, the named ones keep the same 2:1 ratio
+
+use crate::{initial_conditions::InitialCondition, rd_system::SimParams};
+
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub params: SimParams,
+    // an InitialCondition name that shows the regime well
+    pub init: &'static str,
+}
+
+impl Preset {
+    pub fn initial_condition(&self) -> InitialCondition {
+        InitialCondition::from_name(self.init).expect("presets only use built-in names")
+    }
+
+    pub fn find(name: &str) -> Option<&'static Preset> {
+        PRESETS.iter().find(|p| p.name == name)
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        PRESETS.iter().map(|p| p.name).collect()
+    }
+}
+
+const fn pearson(feed: f32, kill: f32) -> SimParams {
+    SimParams {
+        du: 0.2097,
+        dv: 0.105,
+        feed,
+        kill,
+        dt: 1.0,
+    }
+}
+
+// in cycling order
+pub const PRESETS: [Preset; 16] = [
+    Preset {
+        name: "alpha",
+        description: "spiral waves that break up into chaos",
+        params: pearson(0.010, 0.047),
+        init: "pearson",
+    },
+    Preset {
+        name: "beta",
+        description: "chaotic waves",
+        params: pearson(0.026, 0.051),
+        init: "pearson",
+    },
+    Preset {
+        name: "gamma",
+        description: "waves that slowly turn into stripes",
+        params: pearson(0.022, 0.051),
+        init: "pearson",
+    },
+    Preset {
+        name: "delta",
+        description: "hexagonal spots and short stripes",
+        params: pearson(0.030, 0.055),
+        init: "pearson",
+    },
+    Preset {
+        name: "epsilon",
+        description: "chaos of spots and stripes",
+        params: pearson(0.018, 0.055),
+        init: "pearson",
+    },
+    Preset {
+        name: "zeta",
+        description: "pulsating spots",
+        params: pearson(0.025, 0.060),
+        init: "pearson",
+    },
+    Preset {
+        name: "eta",
+        description: "worms that grow and connect",
+        params: pearson(0.034, 0.063),
+        init: "pearson",
+    },
+    Preset {
+        name: "theta",
+        description: "labyrinth of stripes",
+        params: pearson(0.030, 0.057),
+        init: "pearson",
+    },
+    Preset {
+        name: "iota",
+        description: "stripes that fill the space",
+        params: pearson(0.046, 0.0594),
+        init: "pearson",
+    },
+    Preset {
+        name: "kappa",
+        description: "stripes and holes",
+        params: pearson(0.050, 0.063),
+        init: "pearson",
+    },
+    Preset {
+        name: "lambda",
+        description: "spots that divide until they fill the space",
+        params: pearson(0.026, 0.061),
+        init: "pearson",
+    },
+    Preset {
+        name: "mu",
+        description: "stripes growing out of spots",
+        params: pearson(0.046, 0.065),
+        init: "pearson",
+    },
+    Preset {
+        name: "mitosis",
+        description: "cell like spots that keep dividing",
+        params: pearson(0.0367, 0.0649),
+        init: "pearson",
+    },
+    Preset {
+        name: "coral",
+        description: "coral like growth",
+        params: pearson(0.0545, 0.062),
+        init: "spots",
+    },
+    Preset {
+        name: "worms",
+        description: "long worms",
+        params: pearson(0.058, 0.065),
+        init: "spots",
+    },
+    Preset {
+        name: "solitons",
+        description: "stable single spots",
+        params: pearson(0.030, 0.062),
+        init: "spots",
+    },
+];
@@ -1,43 +1,692 @@
 use bytemuck::{Pod, Zeroable};
-use std::{fs, num::NonZeroU64, path::PathBuf, time::Instant};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+};
 
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     *,
 };
 
-use crate::gpu_resources::{FrameContext, GpuResource};
+use crate::{
+    brush::{self, BrushPass, BrushStroke, BrushUniform},
+    initial_conditions::InitialCondition,
+    mask::Mask,
+    param_map::ParamMap,
+    preprocessor,
+    shader_check::{self, ShaderError},
+};
+
+// Pixels (default grid size)
+pub const DEFAULT_HEIGHT: u32 = 1280;
+pub const DEFAULT_WIDTH: u32 = 1280;
+
+// default workgroup size of the compute shader, the brush shader always uses it
+pub const WG_X: u32 = 16;
+pub const WG_Y: u32 = 16;
+// wgpu's default limit for invocations per workgroup
+pub const MAX_WORKGROUP_INVOCATIONS: u32 = 256;
+// wgpu's default max_texture_dimension_2d, both the window and the headless device ask for
+// the default limits so this is the largest grid before there is a device to ask
+pub const MAX_GRID_DIMENSION: u32 = 8192;
+
+// the pipelines that are rebuilt on their own when their shader file changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PipelineKind {
+    Compute,
+    Render,
+    Brush,
+}
 
-// Pixels
-const HEIGHT: u32 = 1280;
-const WIDTH: u32 = 1280;
+impl PipelineKind {
+    pub const ALL: [PipelineKind; 3] = [Self::Compute, Self::Render, Self::Brush];
 
-const WG_X: u32 = 16;
-const WG_Y: u32 = 16;
+    // relative to the crate root
+    pub fn shader_path(self) -> &'static str {
+        match self {
+            Self::Compute => "shaders/rd_compute.wgsl",
+            Self::Render => "shaders/rd_display.wgsl",
+            Self::Brush => "shaders/brush.wgsl",
+        }
+    }
+}
 
 // helper function to have a dynamical shader address
 // so the source is not "hard coded" in the compile time
-fn load_ablsolute_path(relative_path: &str) -> String {
-    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative_path); // making absolute path
-    fs::read_to_string(&path)
-        .unwrap_or_else(|e| panic!("Failed to read shader {:?}\nError: {}", path, e))
+// the source goes through the preprocessor (#include, #define, #ifdef) with `defines` from Rust
+// and is validated by naga, a broken shader is an error instead of a wgpu panic later
+pub(crate) fn load_ablsolute_path(
+    relative_path: &str,
+    defines: &[(&str, String)],
+) -> Result<String, ShaderError> {
+    let shader = shader_check::read_shader(relative_path, defines)?; // making absolute path
+    shader_check::validate_wgsl(&shader)?;
+    Ok(shader.source)
+}
+
+// Gray-Scott parameters that can be changed while the simulation is running
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimParams {
+    pub du: f32,   // diffusion rate for substance U
+    pub dv: f32,   // diffusion rate for substance V
+    pub feed: f32, // Feed rate of U
+    pub kill: f32, // V's killing rate
+    pub dt: f32,   // time step of one compute dispatch
+}
+
+impl Default for SimParams {
+    // the parameters of the first pattern in the README
+    fn default() -> Self {
+        Self {
+            du: 0.19,
+            dv: 0.08,
+            feed: 0.0345,
+            kill: 0.062,
+            dt: 0.5,
+        }
+    }
+}
+
+// color theme of the display shader
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Palette {
+    // the original cosine palette
+    #[default]
+    Cosine,
+    Grayscale,
+    Fire,
+    Ocean,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 4] = [Self::Cosine, Self::Grayscale, Self::Fire, Self::Ocean];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cosine => "cosine",
+            Self::Grayscale => "grayscale",
+            Self::Fire => "fire",
+            Self::Ocean => "ocean",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+// what the laplacian sees outside the grid
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Boundary {
+    // zero flux, the edge pixel continues outside (the only behaviour before there was a choice)
+    #[default]
+    Neumann,
+    // wraps around, the field tiles seamlessly
+    Periodic,
+    // fixed U and V just outside the grid, the edge pixels themselves still react
+    Dirichlet {
+        u: f32,
+        v: f32,
+    },
+}
+
+impl Boundary {
+    pub const NAMES: [&'static str; 3] = ["neumann", "periodic", "dirichlet"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Neumann => "neumann",
+            Self::Periodic => "periodic",
+            Self::Dirichlet { .. } => "dirichlet",
+        }
+    }
+
+    // "neumann", "periodic", "dirichlet" (U = 1, V = 0 outside) or "dirichlet:<U>,<V>"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, values) = match spec.split_once(':') {
+            Some((name, values)) => (name, Some(values)),
+            None => (spec, None),
+        };
+        match (name, values) {
+            ("neumann", None) => Ok(Self::Neumann),
+            ("periodic", None) => Ok(Self::Periodic),
+            ("dirichlet", None) => Ok(Self::Dirichlet { u: 1.0, v: 0.0 }),
+            ("dirichlet", Some(values)) => {
+                let invalid = || format!("Invalid boundary '{}', expected dirichlet:<U>,<V>", spec);
+                let (u, v) = values.split_once(',').ok_or_else(invalid)?;
+                let u = u.trim().parse().map_err(|_| invalid())?;
+                let v = v.trim().parse().map_err(|_| invalid())?;
+                Ok(Self::Dirichlet { u, v })
+            }
+            _ => Err(format!(
+                "Unknown boundary '{}', expected one of {} or dirichlet:<U>,<V>",
+                spec,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+// the form parse reads
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dirichlet { u, v } => write!(f, "dirichlet:{},{}", u, v),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+// the discrete laplacian of the compute shader, baked in when the pipeline is built
+//
+//   corner  edge  corner          far
+//    edge  center  edge      far edge center edge far
+//   corner  edge  corner          far
+//
+// "far" are the pixels two away along the axes (the 13-point neighbourhood)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stencil {
+    // the original one, cheap but the grid shows at larger dt
+    #[default]
+    FivePoint,
+    // Patra and Karttunen 2006, 9 points with an isotropic error term
+    Isotropic,
+    // Oono and Puri 1988, also 9 points and isotropic, a little less accurate
+    OonoPuri,
+    // the 3x3 convolution from Karl Sims' tutorial, it is 0.3 times the laplacian
+    // so it needs larger diffusion rates (he uses DU = 1, DV = 0.5)
+    Sims,
+    // fourth order accurate in the 13-point neighbourhood, the corner weights come out as 0
+    FourthOrder,
 }
 
-// time
+// weights of a stencil, the same ones for all pixels of a kind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StencilWeights {
+    pub center: f32,
+    pub edge: f32,
+    pub corner: f32,
+    pub far: f32,
+}
+
+impl Stencil {
+    pub const ALL: [Stencil; 5] = [
+        Self::FivePoint,
+        Self::Isotropic,
+        Self::OonoPuri,
+        Self::Sims,
+        Self::FourthOrder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FivePoint => "five-point",
+            Self::Isotropic => "isotropic",
+            Self::OonoPuri => "oono-puri",
+            Self::Sims => "sims",
+            Self::FourthOrder => "fourth-order",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    pub fn weights(&self) -> StencilWeights {
+        let (center, edge, corner, far) = match self {
+            Self::FivePoint => (-4.0, 1.0, 0.0, 0.0),
+            Self::Isotropic => (-10.0 / 3.0, 2.0 / 3.0, 1.0 / 6.0, 0.0),
+            Self::OonoPuri => (-3.0, 0.5, 0.25, 0.0),
+            Self::Sims => (-1.0, 0.2, 0.05, 0.0),
+            // -1/12, 4/3, -5/2, 4/3, -1/12 along both axes
+            Self::FourthOrder => (-5.0, 4.0 / 3.0, 0.0, -1.0 / 12.0),
+        };
+        StencilWeights {
+            center,
+            edge,
+            corner,
+            far,
+        }
+    }
+
+    // the weights as #defines for common.wgsl, pixels with weight 0 are not read at all
+    pub fn shader_defines(&self) -> Vec<(&'static str, String)> {
+        let weights = self.weights();
+        [
+            ("STENCIL_CENTER", weights.center),
+            ("STENCIL_EDGE", weights.edge),
+            ("STENCIL_CORNER", weights.corner),
+            ("STENCIL_FAR", weights.far),
+        ]
+        .into_iter()
+        .filter(|(_, weight)| *weight != 0.0)
+        .map(|(name, weight)| (name, format!("{:?}", weight)))
+        .collect()
+    }
+
+    // how fast the quickest Fourier mode decays under this laplacian, 8 for five-point
+    // (the checkerboard). it is what limits dt for the explicit integrators (stability.rs)
+    pub fn max_eigenvalue(&self) -> f32 {
+        self.symbol_max(|symbol| -symbol)
+    }
+
+    // largest dt * D the Jacobi iterations of IMEX converge for, None if they always do
+    // the iteration multiplies a Fourier mode by dt D (symbol - center) / (1 + dt D |center|),
+    // that stays below 1 for any dt when no |symbol - center| is larger than |center|.
+    // fourth-order reaches 17/3 against 5 at the checkerboard, so dt * D < 1.5
+    pub fn jacobi_limit(&self) -> Option<f32> {
+        let center = self.weights().center.abs();
+        let off_center = self.symbol_max(|symbol| (symbol + center).abs());
+        // isotropic is exactly at |center|, the sampled cosines round a little above it
+        (off_center - center > 1e-4).then(|| 1.0 / (off_center - center))
+    }
+
+    // largest f(symbol) of the stencil applied to cos(kx x) cos(ky y),
+    // sampled over kx, ky in [0, pi]
+    fn symbol_max(&self, f: impl Fn(f32) -> f32) -> f32 {
+        const SAMPLES: u32 = 64;
+        let weights = self.weights();
+        let cosines: Vec<f32> = (0..=SAMPLES)
+            .map(|i| (i as f32 * std::f32::consts::PI / SAMPLES as f32).cos())
+            .collect();
+
+        let mut max = 0.0f32;
+        for &cx in &cosines {
+            for &cy in &cosines {
+                // cos(2k) = 2 cos(k)^2 - 1 for the pixels two away
+                let far = 2.0 * cx * cx - 1.0 + 2.0 * cy * cy - 1.0;
+                let symbol = weights.center
+                    + 2.0 * weights.edge * (cx + cy)
+                    + 4.0 * weights.corner * cx * cy
+                    + 2.0 * weights.far * far;
+                max = max.max(f(symbol));
+            }
+        }
+        max
+    }
+}
+
+// Jacobi iterations of the IMEX integrator when none are given
+pub const DEFAULT_JACOBI_ITERATIONS: u32 = 20;
+
+// how a step moves the field forward by dt
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Integrator {
+    // explicit Euler, one laplacian per step (the only one before there was a choice)
+    #[default]
+    Euler,
+    // Heun's method (explicit trapezoidal rule), second order with 2 laplacians per step
+    Heun,
+    // the classic fourth order Runge-Kutta with 4 laplacians per step
+    Rk4,
+    // reaction explicit and diffusion implicit, (1 - dt D L) y' = y + dt R(y)
+    // the linear system is solved with Jacobi iterations, diffusion is stable for any dt
+    Imex {
+        iterations: u32,
+    },
+}
+
+// what one compute dispatch of a step does, the entry points of rd_compute.wgsl
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StageKind {
+    // a Runge-Kutta stage (Euler is one with a single stage)
+    RungeKutta,
+    // y + dt R(y), the right hand side of the IMEX system
+    ImexReaction,
+    // one Jacobi iteration of the IMEX system
+    ImexJacobi,
+}
+
+impl StageKind {
+    pub fn entry_point(&self) -> &'static str {
+        match self {
+            Self::RungeKutta => "main",
+            Self::ImexReaction => "imex_reaction",
+            Self::ImexJacobi => "imex_jacobi",
+        }
+    }
+}
+
+// one compute dispatch of a step
+// a stage reads the field at the start of the step and what the stage before wrote
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stage {
+    pub kind: StageKind,
+    pub first: bool,
+    // writes the field, all others write a stage texture
+    pub last: bool,
+    // Runge-Kutta: weight of this stage's rate in the step
+    pub weight: f32,
+    // Runge-Kutta: the next stage evaluates the rate at y + next_offset * dt * rate
+    pub next_offset: f32,
+}
+
+impl Integrator {
+    pub const NAMES: [&'static str; 4] = ["euler", "heun", "rk4", "imex"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Euler => "euler",
+            Self::Heun => "heun",
+            Self::Rk4 => "rk4",
+            Self::Imex { .. } => "imex",
+        }
+    }
+
+    // a name from NAMES or "imex:<iterations>"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, value) = match spec.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (spec, None),
+        };
+        match (name, value) {
+            ("euler", None) => Ok(Self::Euler),
+            ("heun", None) => Ok(Self::Heun),
+            ("rk4", None) => Ok(Self::Rk4),
+            ("imex", None) => Ok(Self::Imex {
+                iterations: DEFAULT_JACOBI_ITERATIONS,
+            }),
+            ("imex", Some(value)) => match value.trim().parse() {
+                Ok(iterations) if iterations > 0 => Ok(Self::Imex { iterations }),
+                _ => Err(format!(
+                    "Invalid integrator '{}', expected imex:<iterations> with iterations > 0",
+                    spec
+                )),
+            },
+            _ => Err(format!(
+                "Unknown integrator '{}', expected one of {} or imex:<iterations>",
+                spec,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+
+    // how far the method is stable along the negative real axis, |R(z)| <= 1 for z in
+    // [-interval, 0]. None for IMEX, its implicit diffusion is stable for any dt
+    pub fn stability_interval(&self) -> Option<f32> {
+        match self {
+            Self::Euler => Some(2.0),
+            // measured, not derived. on the axis it is 2 like Euler, but the region is only
+            // a sliver that far out and the reaction moves the eigenvalues off the axis.
+            // with the default parameters on 128x128 Heun broke at 1.65 (blob), 1.7 (spots)
+            // and 1.74 (noise), 1.6 held for all of them
+            Self::Heun => Some(1.6),
+            Self::Rk4 => Some(2.785),
+            // the diffusion is implicit, only the Jacobi iterations can limit dt (jacobi_limit)
+            Self::Imex { .. } => None,
+        }
+    }
+
+    // the dispatches of one step in order
+    pub fn stages(&self) -> Vec<Stage> {
+        let runge_kutta = |weights: &[(f32, f32)]| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, &(weight, next_offset))| Stage {
+                    kind: StageKind::RungeKutta,
+                    first: i == 0,
+                    last: i == weights.len() - 1,
+                    weight,
+                    next_offset,
+                })
+                .collect()
+        };
+
+        match self {
+            Self::Euler => runge_kutta(&[(1.0, 0.0)]),
+            Self::Heun => runge_kutta(&[(0.5, 1.0), (0.5, 0.0)]),
+            Self::Rk4 => runge_kutta(&[
+                (1.0 / 6.0, 0.5),
+                (1.0 / 3.0, 0.5),
+                (1.0 / 3.0, 1.0),
+                (1.0 / 6.0, 0.0),
+            ]),
+            Self::Imex { iterations } => {
+                let iterations = (*iterations).max(1);
+                let stage = |kind, first, last| Stage {
+                    kind,
+                    first,
+                    last,
+                    weight: 0.0,
+                    next_offset: 0.0,
+                };
+                let mut stages = vec![stage(StageKind::ImexReaction, true, false)];
+                stages.extend(
+                    (0..iterations)
+                        .map(|i| stage(StageKind::ImexJacobi, false, i == iterations - 1)),
+                );
+                stages
+            }
+        }
+    }
+}
+
+// the form parse reads
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Imex { iterations } => write!(f, "imex:{}", iterations),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+// display settings as the GPU sees them
+// this lives in group 0 binding 2 of the render pipeline
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct DisplayUniform {
+    palette: u32,   // 4 byte, index into Palette::ALL
+    _pad: [u32; 3], // 12 byte
+}
+
+impl DisplayUniform {
+    fn new(palette: Palette) -> Self {
+        Self {
+            palette: palette as u32,
+            _pad: [0; 3],
+        }
+    }
+}
+
+// parameters as the GPU sees them
 // this lives in group 0 binding 0
 #[repr(C)] // format expected by the gpu
 #[derive(Clone, Copy, Pod, Zeroable)]
-struct TimeUniform {
+struct ParamsUniform {
     // 16 byte alignment needed
-    dt: f32,        // 4 byte
-    _pad: [f32; 3], // 12 byte
+    du: f32,                // 4 byte
+    dv: f32,                // 4 byte
+    feed: f32,              // 4 byte
+    kill: f32,              // 4 byte
+    dt: f32,                // 4 byte
+    use_param_map: u32,     // 4 byte, 1 = read feed/kill/du/dv from the parameter texture
+    boundary: u32,          // 4 byte, BOUNDARY_* in common.wgsl
+    _pad: u32,              // 4 byte, the vec2 below starts at 8 byte
+    boundary_u_v: [f32; 2], // 8 byte
+    _pad_2: [u32; 2],       // 8 byte
 }
+
+impl ParamsUniform {
+    fn new(params: &SimParams, use_param_map: bool, boundary: Boundary) -> Self {
+        let (boundary, boundary_u_v) = match boundary {
+            Boundary::Neumann => (0, [0.0; 2]),
+            Boundary::Periodic => (1, [0.0; 2]),
+            Boundary::Dirichlet { u, v } => (2, [u, v]),
+        };
+        Self {
+            du: params.du,
+            dv: params.dv,
+            feed: params.feed,
+            kill: params.kill,
+            dt: params.dt,
+            use_param_map: use_param_map as u32,
+            boundary,
+            _pad: 0,
+            boundary_u_v,
+            _pad_2: [0; 2],
+        }
+    }
+}
+
+// what the compute shader found outside [0, 1] before clamping, group 0 binding 6
+// pixels counts them over all steps since the last reset, first is the smallest y * width + x
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
+pub struct DivergenceFlags {
+    pub pixels: u32,
+    pub first: u32,
+}
+
+impl DivergenceFlags {
+    pub const CLEAR: Self = Self {
+        pixels: 0,
+        first: u32::MAX,
+    };
+}
+
+// uploads a full f32 image (RGBA32Float or R32Float) into a texture of the same size
+fn write_f32s(queue: &Queue, texture: &Texture, data: &[f32]) {
+    let (width, height) = (texture.width(), texture.height());
+    // 4 byte per channel
+    let bytes_per_pixel = texture.format().block_copy_size(None).unwrap_or(16);
+    queue.write_texture(
+        TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        bytemuck::cast_slice(data),
+        TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_pixel * width),
+            rows_per_image: Some(height),
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+// values for the pipeline-overridable constants of rd_compute.wgsl
+fn stage_constants(size: [u32; 2], stage: &Stage, detect: bool) -> [(&'static str, f64); 7] {
+    [
+        ("WG_X", size[0] as f64),
+        ("WG_Y", size[1] as f64),
+        ("FIRST_STAGE", stage.first as u32 as f64),
+        ("LAST_STAGE", stage.last as u32 as f64),
+        ("STAGE_WEIGHT", stage.weight as f64),
+        ("NEXT_OFFSET", stage.next_offset as f64),
+        ("DETECT_DIVERGENCE", detect as u32 as f64),
+    ]
+}
+
+// one compute pipeline per stage of a step, equal stages (the Jacobi iterations) share one
+// every pipeline gets its own shader module, pipelines from one module all ended up with
+// the constants of the first one (seen on llvmpipe)
+fn create_compute_pipelines(
+    device: &Device,
+    source: &str,
+    layout: &PipelineLayout,
+    workgroup_size: [u32; 2],
+    integrator: Integrator,
+    detect_divergence: bool,
+) -> Vec<ComputePipeline> {
+    let mut built: Vec<(Stage, ComputePipeline)> = Vec::new();
+    integrator
+        .stages()
+        .iter()
+        .map(|stage| {
+            if let Some((_, pipeline)) = built.iter().find(|(s, _)| s == stage) {
+                return pipeline.clone();
+            }
+            let module = device.create_shader_module(ShaderModuleDescriptor {
+                label: Some("Compute Shader Module"),
+                source: ShaderSource::Wgsl(source.into()),
+            });
+            let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Compute Pipeline"),
+                layout: Some(layout),
+                module: &module,
+                entry_point: Some(stage.kind.entry_point()),
+                compilation_options: PipelineCompilationOptions {
+                    constants: &stage_constants(workgroup_size, stage, detect_divergence),
+                    ..Default::default()
+                },
+                cache: None,
+            });
+            built.push((*stage, pipeline.clone()));
+            pipeline
+        })
+        .collect()
+}
+
+// max_dimension is MAX_GRID_DIMENSION or device.limits().max_texture_dimension_2d
+pub fn validate_grid_size(width: u32, height: u32, max_dimension: u32) -> Result<(), String> {
+    if width == 0 || height == 0 || width > max_dimension || height > max_dimension {
+        return Err(format!(
+            "Grid size {}x{} is invalid, both need to be between 1 and {}",
+            width, height, max_dimension
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_workgroup_size(size: [u32; 2]) -> Result<(), String> {
+    if size[0] == 0
+        || size[1] == 0
+        || size[0] as u64 * size[1] as u64 > MAX_WORKGROUP_INVOCATIONS as u64
+    {
+        return Err(format!(
+            "Workgroup size {}x{} is invalid, both need to be at least 1 and together at most {}",
+            size[0], size[1], MAX_WORKGROUP_INVOCATIONS
+        ));
+    }
+    Ok(())
+}
+
+// render pass into the view, cleared to black
+pub fn begin_render_pass<'a>(
+    encoder: &'a mut CommandEncoder,
+    view: &'a TextureView,
+) -> RenderPass<'a> {
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::BLACK),
+                store: StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
 // Communication between the system and GPU
 pub struct ReactionDiffusionSystem {
+    // grid size in pixels
+    pub width: u32,
+    pub height: u32,
+
     // uniform
-    pub time_buffer: Buffer,
-    pub start_instant: Instant,
-    pub last_time: f32,
+    pub params: SimParams,
+    pub params_buffer: Buffer,
 
     // texture source lives in group 0 binding 1
     // using two textures one reads while other writes
@@ -48,48 +697,91 @@ pub struct ReactionDiffusionSystem {
     pub texture_view_2: TextureView,
     pub sampler: Sampler,
 
+    // per pixel feed/kill/du/dv lives in group 0 binding 3
+    // only used by the shader when use_param_map is set
+    pub param_texture: Texture,
+    pub param_texture_view: TextureView,
+    pub use_param_map: bool,
+    // largest du or dv the parameter map sets itself, for the stability limit
+    pub param_map_diffusion: f32,
+    // some pixels of the map use the global du/dv
+    pub param_map_uses_global: bool,
+    // what the laplacian sees outside the grid, sent with the params every step
+    pub boundary: Boundary,
+
+    // walls (1) inside the grid live in compute group 0 binding 4, R32Float
+    // the brush writes into it and the display shader draws it
+    pub mask_texture: Texture,
+    pub mask_texture_view: TextureView,
+
+    // intermediate results of the stages of a step (RK4, IMEX, ...), RGBA32Float
+    // RG is the state the next stage evaluates, BA the sum of the rates or the IMEX right hand side
+    pub stage_texture_1: Texture,
+    pub stage_texture_2: Texture,
+
     // compute
     pub compute_bgl: BindGroupLayout,
+    // the last stage of a step, writes the other source
     pub compute_bg_1_to_2: BindGroup,
     pub compute_bg_2_to_1: BindGroup,
-    pub compute_pipeline: ComputePipeline,
+    // the stages before it, [0] writes stage texture 1 and [1] stage texture 2
+    pub compute_bg_stages_from_1: [BindGroup; 2],
+    pub compute_bg_stages_from_2: [BindGroup; 2],
+    // one per stage of a step, in order
+    pub compute_pipelines: Vec<ComputePipeline>,
+    // DivergenceFlags, written by the last stage when detect_divergence is on
+    pub divergence_buffer: Buffer,
+    // the `override` DETECT_DIVERGENCE of the compute shader
+    pub detect_divergence: bool,
+    // the `override` WG_X/WG_Y of the compute shader
+    pub workgroup_size: [u32; 2],
+    // the laplacian, #defines of the compute shader
+    pub stencil: Stencil,
+    // the stages of a step, one dispatch each
+    pub integrator: Integrator,
+
+    // mouse painting into the current source
+    pub brush: BrushPass,
 
     // rendering
     pub render_bgl: BindGroupLayout,
     pub render_bg_from_1: BindGroup,
     pub render_bg_from_2: BindGroup,
     pub render_pipeline: RenderPipeline,
+    pub palette: Palette,
+    pub display_buffer: Buffer,
+    // format of the views we render into
+    pub target_format: TextureFormat,
 
     // ping or pong :)
     pub use_1_as_source: bool,
 }
 
 impl ReactionDiffusionSystem {
-    pub fn new(gpu_res: &GpuResource) -> Self {
-        // importing resources
-        let device_m = &gpu_res.device;
-
-        // time uniform buffer
-        let time_uniform = TimeUniform {
-            dt: 0.0,
-            _pad: [0.0; 3],
-        };
-
-        let time_buffer = device_m.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Time Uniform Buffer"),
-            contents: bytemuck::bytes_of(&time_uniform),
+    pub fn new(
+        device_m: &Device,
+        queue: &Queue,
+        target_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        // zero sized or too large textures would be a wgpu panic
+        validate_grid_size(width, height, device_m.limits().max_texture_dimension_2d)?;
+
+        // parameter uniform buffer
+        let params = SimParams::default();
+        let params_buffer = device_m.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&ParamsUniform::new(&params, false, Boundary::default())),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let start_instant = Instant::now();
-        let last_time: f32 = 0.0;
-
         // create textures
         let texture_desc = TextureDescriptor {
             label: Some("Texture Descriptor"),
             size: Extent3d {
-                width: WIDTH,
-                height: HEIGHT,
+                width,
+                height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
@@ -98,7 +790,8 @@ impl ReactionDiffusionSystem {
             format: TextureFormat::Rgba32Float,
             usage: TextureUsages::STORAGE_BINDING
                 | TextureUsages::TEXTURE_BINDING
-                | TextureUsages::COPY_DST,
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC, // needed to read the field back
             view_formats: &[],
         };
 
@@ -115,6 +808,67 @@ impl ReactionDiffusionSystem {
         let texture_view_1 = texture_source_1.create_view(&TextureViewDescriptor::default());
         let texture_view_2 = texture_source_2.create_view(&TextureViewDescriptor::default());
 
+        // only the compute shader touches these, nothing to upload
+        let stage_texture_desc = TextureDescriptor {
+            label: Some("Stage Texture 1"),
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            ..texture_desc.clone()
+        };
+        let stage_texture_1 = device_m.create_texture(&stage_texture_desc);
+        let stage_texture_2 = device_m.create_texture(&TextureDescriptor {
+            label: Some("Stage Texture 2"),
+            ..stage_texture_desc
+        });
+        let stage_view_1 = stage_texture_1.create_view(&TextureViewDescriptor::default());
+        let stage_view_2 = stage_texture_2.create_view(&TextureViewDescriptor::default());
+
+        let divergence_buffer = device_m.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Divergence Buffer"),
+            contents: bytemuck::bytes_of(&DivergenceFlags::CLEAR),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        });
+
+        // parameter map, only read not written by the compute shader
+        let param_texture = device_m.create_texture(&TextureDescriptor {
+            label: Some("Parameter Texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba32Float,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC, // read back for the CPU reference and checkpoints
+            view_formats: &[],
+        });
+        let param_texture_view = param_texture.create_view(&TextureViewDescriptor::default());
+        let param_map = ParamMap::uniform(width, height, &params);
+        write_f32s(queue, &param_texture, &param_map.data);
+
+        // no walls until a mask is set or painted
+        let mask_texture = device_m.create_texture(&TextureDescriptor {
+            label: Some("Mask Texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING // the wall brush
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC, // read back for checkpoints
+            view_formats: &[],
+        });
+        let mask_texture_view = mask_texture.create_view(&TextureViewDescriptor::default());
+
         let sampler = device_m.create_sampler(&SamplerDescriptor {
             label: Some("Sampler Descriptor"),
             address_mode_u: AddressMode::ClampToEdge,
@@ -127,56 +881,27 @@ impl ReactionDiffusionSystem {
         });
 
         // initialize a blob in the middle
-        // TODO make a separate file for blob
-        let mut data = vec![0.0_f32; (WIDTH * HEIGHT * 4) as usize]; // each pixel has 4 values RGBA
-
-        // loop over all the pixels
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let pixel_idx = ((y * WIDTH + x) * 4) as usize;
-
-                // element U everywhere
-                // element V only in blob
-                let u = 1.0_f32;
-                let mut v = 0.0_f32;
-
-                // blob in the center for element V
-                let center_x = WIDTH as i32 / 2;
-                let center_y = HEIGHT as i32 / 2;
-
-                let dist_x = x as i32 - center_x;
-                let dist_y = y as i32 - center_y;
-
-                if dist_x.abs() * dist_x.abs() + dist_y.abs() * dist_y.abs() < 100 {
-                    // TODO check out standard initializations
-                    v = 1.0; // add element V to the area
-                }
-
-                // write the data to the channels
-                data[pixel_idx + 0] = u;
-                data[pixel_idx + 1] = v;
-                data[pixel_idx + 2] = 0.0;
-                data[pixel_idx + 3] = 1.0;
-            }
-        }
+        let data = InitialCondition::default()
+            .generate(width, height, 0)
+            .expect("the blob can not fail");
 
         let data_bytes: &[u8] = bytemuck::cast_slice(&data);
 
         let layout = TexelCopyBufferLayout {
             offset: 0,
             // RGBA32Float = 4 channel * 4 byte per pixel
-            bytes_per_row: Some(4 * 4 * WIDTH),
-            rows_per_image: Some(HEIGHT),
+            bytes_per_row: Some(4 * 4 * width),
+            rows_per_image: Some(height),
         };
 
         let extent = Extent3d {
-            width: WIDTH,
-            height: HEIGHT,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
         // queue source 1
-        gpu_res.queue.write_texture(
+        queue.write_texture(
             TexelCopyTextureInfo {
                 texture: &texture_source_1,
                 mip_level: 0,
@@ -188,7 +913,7 @@ impl ReactionDiffusionSystem {
             extent,
         );
         // queue source 2
-        gpu_res.queue.write_texture(
+        queue.write_texture(
             TexelCopyTextureInfo {
                 texture: &texture_source_2,
                 mip_level: 0,
@@ -203,117 +928,226 @@ impl ReactionDiffusionSystem {
         // shader modules
 
         // a run time shader loader instead of compile time which makes the program ready for hot reload
-        let compute_shader_path = load_ablsolute_path("shaders/rd_compute.wgsl");
-        let render_shader_path = load_ablsolute_path("shaders/rd_display.wgsl");
-
-        let compute_shader = device_m.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Compute Shader Module"),
-            source: ShaderSource::Wgsl(compute_shader_path.into()),
-        });
-        let render_shader = device_m.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Render Shader Module"),
-            source: ShaderSource::Wgsl(render_shader_path.into()),
-        });
+        let stencil = Stencil::default();
+        // the same checks as a hot reload, so a broken shader on disk ends up in the error
+        // of a resize or a checkpoint load and not in a panic
+        let shader_error = |e: ShaderError| format!("Shader error in {}", e);
+        let compute_shader_path = load_ablsolute_path(
+            PipelineKind::Compute.shader_path(),
+            &stencil.shader_defines(),
+        )
+        .map_err(shader_error)?;
+        let render_shader_path =
+            load_ablsolute_path(PipelineKind::Render.shader_path(), &[]).map_err(shader_error)?;
 
         // compute
-        let compute_bgl =
-            device_m.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("Compute Bing Group Layout"),
-                entries: &[
-                    BindGroupLayoutEntry {
-                        // time uniform buffer binding 0
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: NonZeroU64::new(
-                                std::mem::size_of::<TimeUniform>() as u64
-                            ),
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        // source (sampled)
-                        binding: 1,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Texture {
-                            sample_type: TextureSampleType::Float { filterable: false },
-                            view_dimension: TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        // dst (storage)
-                        binding: 2,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::WriteOnly,
-                            format: TextureFormat::Rgba32Float,
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    },
-                ],
-            });
-
-        // write to 2
-        let compute_bg_1_to_2 = device_m.create_bind_group(&BindGroupDescriptor {
-            label: Some("Compute Bind Group"),
-            layout: &compute_bgl,
+        let compute_bgl = device_m.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Compute Bing Group Layout"),
             entries: &[
-                BindGroupEntry {
+                BindGroupLayoutEntry {
+                    // params uniform buffer binding 0
                     binding: 0,
-                    resource: time_buffer.as_entire_binding(),
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(
+                            std::mem::size_of::<ParamsUniform>() as u64
+                        ),
+                    },
+                    count: None,
                 },
-                BindGroupEntry {
+                BindGroupLayoutEntry {
+                    // source (sampled)
                     binding: 1,
-                    resource: BindingResource::TextureView(&texture_view_1),
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
                 },
-                BindGroupEntry {
+                BindGroupLayoutEntry {
+                    // dst (storage)
                     binding: 2,
-                    resource: BindingResource::TextureView(&texture_view_2),
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba32Float,
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
                 },
-            ],
-        });
-
-        // write to 1
-        let compute_bg_2_to_1 = device_m.create_bind_group(&BindGroupDescriptor {
-            label: Some("Compute Bind Group"),
-            layout: &compute_bgl,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: time_buffer.as_entire_binding(),
+                BindGroupLayoutEntry {
+                    // parameter map (sampled)
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
                 },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&texture_view_2),
+                BindGroupLayoutEntry {
+                    // walls (sampled)
+                    binding: 4,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
                 },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::TextureView(&texture_view_1),
+                BindGroupLayoutEntry {
+                    // what the stage before wrote (sampled)
+                    binding: 5,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    // divergence flags (storage)
+                    binding: 6,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(
+                            std::mem::size_of::<DivergenceFlags>() as u64
+                        ),
+                    },
+                    count: None,
                 },
             ],
         });
 
+        // the source, the stage texture to read and the texture to write
+        let compute_bg = |label, src: &TextureView, stage: &TextureView, dst: &TextureView| {
+            device_m.create_bind_group(&BindGroupDescriptor {
+                label: Some(label),
+                layout: &compute_bgl,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(src),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(dst),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&param_texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: BindingResource::TextureView(&mask_texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 5,
+                        resource: BindingResource::TextureView(stage),
+                    },
+                    BindGroupEntry {
+                        binding: 6,
+                        resource: divergence_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        // write to 2
+        let compute_bg_1_to_2 = compute_bg(
+            "Compute Bind Group",
+            &texture_view_1,
+            &stage_view_1,
+            &texture_view_2,
+        );
+        // write to 1
+        let compute_bg_2_to_1 = compute_bg(
+            "Compute Bind Group",
+            &texture_view_2,
+            &stage_view_1,
+            &texture_view_1,
+        );
+        // from one stage texture into the other
+        let compute_bg_stages_from_1 = [
+            compute_bg(
+                "Compute Stage Bind Group",
+                &texture_view_1,
+                &stage_view_2,
+                &stage_view_1,
+            ),
+            compute_bg(
+                "Compute Stage Bind Group",
+                &texture_view_1,
+                &stage_view_1,
+                &stage_view_2,
+            ),
+        ];
+        let compute_bg_stages_from_2 = [
+            compute_bg(
+                "Compute Stage Bind Group",
+                &texture_view_2,
+                &stage_view_2,
+                &stage_view_1,
+            ),
+            compute_bg(
+                "Compute Stage Bind Group",
+                &texture_view_2,
+                &stage_view_1,
+                &stage_view_2,
+            ),
+        ];
+
         let compute_pipeline_layout = device_m.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
             bind_group_layouts: &[&compute_bgl],
             push_constant_ranges: &[],
         });
 
-        let compute_pipeline = device_m.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("Compute Pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: Some("main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            cache: None,
-        });
+        let workgroup_size = [WG_X, WG_Y];
+        let integrator = Integrator::default();
+        // the compute shader modules are made per stage
+        let compute_pipelines =
+            shader_check::create_checked(device_m, PipelineKind::Compute.shader_path(), || {
+                create_compute_pipelines(
+                    device_m,
+                    &compute_shader_path,
+                    &compute_pipeline_layout,
+                    workgroup_size,
+                    integrator,
+                    false,
+                )
+            })
+            .map_err(shader_error)?;
+
+        let brush = BrushPass::new(
+            device_m,
+            &texture_view_1,
+            &texture_view_2,
+            &mask_texture_view,
+        )
+        .map_err(shader_error)?;
 
         // rendering
+        let palette = Palette::default();
+        let display_buffer = device_m.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Display Uniform Buffer"),
+            contents: bytemuck::bytes_of(&DisplayUniform::new(palette)),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
         let render_bgl = device_m.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Render Bind Group Layout"),
             entries: &[
@@ -335,6 +1169,30 @@ impl ReactionDiffusionSystem {
                     ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
                     count: None,
                 },
+                BindGroupLayoutEntry {
+                    // display uniform
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(
+                            std::mem::size_of::<DisplayUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    // walls, drawn over the field
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -350,6 +1208,14 @@ impl ReactionDiffusionSystem {
                     binding: 1,
                     resource: BindingResource::Sampler(&sampler),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: display_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&mask_texture_view),
+                },
             ],
         });
 
@@ -365,6 +1231,14 @@ impl ReactionDiffusionSystem {
                     binding: 1,
                     resource: BindingResource::Sampler(&sampler),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: display_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&mask_texture_view),
+                },
             ],
         });
 
@@ -374,36 +1248,47 @@ impl ReactionDiffusionSystem {
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device_m.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Rendering Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &render_shader,
-                entry_point: Some("vs_main"),
-                compilation_options: PipelineCompilationOptions::default(),
-                buffers: &[],
-            },
-            primitive: PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: MultisampleState::default(),
-            fragment: Some(FragmentState {
-                module: &render_shader,
-                entry_point: Some("fs_main"),
-                compilation_options: PipelineCompilationOptions::default(),
-                targets: &[Some(ColorTargetState {
-                    format: gpu_res.surface_format(),
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            multiview: None,
-            cache: None,
-        });
+        let render_pipeline =
+            shader_check::create_checked(device_m, PipelineKind::Render.shader_path(), || {
+                let render_shader = device_m.create_shader_module(ShaderModuleDescriptor {
+                    label: Some("Render Shader Module"),
+                    source: ShaderSource::Wgsl(render_shader_path.into()),
+                });
 
-        Self {
-            time_buffer,
-            start_instant,
-            last_time,
+                device_m.create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some("Rendering Pipeline"),
+                    layout: Some(&render_pipeline_layout),
+                    vertex: VertexState {
+                        module: &render_shader,
+                        entry_point: Some("vs_main"),
+                        compilation_options: PipelineCompilationOptions::default(),
+                        buffers: &[],
+                    },
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    fragment: Some(FragmentState {
+                        module: &render_shader,
+                        entry_point: Some("fs_main"),
+                        compilation_options: PipelineCompilationOptions::default(),
+                        targets: &[Some(ColorTargetState {
+                            format: target_format,
+                            blend: Some(BlendState::REPLACE),
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview: None,
+                    cache: None,
+                })
+            })
+            .map_err(shader_error)?;
+
+        Ok(Self {
+            width,
+            height,
+
+            params,
+            params_buffer,
 
             texture_source_1,
             texture_source_2,
@@ -411,162 +1296,558 @@ impl ReactionDiffusionSystem {
             texture_view_2,
             sampler,
 
+            param_texture,
+            param_texture_view,
+            use_param_map: false,
+            param_map_diffusion: 0.0,
+            param_map_uses_global: false,
+            boundary: Boundary::default(),
+
+            mask_texture,
+            mask_texture_view,
+
+            stage_texture_1,
+            stage_texture_2,
+
             compute_bgl,
             compute_bg_1_to_2,
             compute_bg_2_to_1,
-            compute_pipeline,
+            compute_bg_stages_from_1,
+            compute_bg_stages_from_2,
+            compute_pipelines,
+            divergence_buffer,
+            detect_divergence: false,
+            workgroup_size,
+            stencil,
+            integrator,
+
+            brush,
 
             render_bgl,
             render_bg_from_1,
             render_bg_from_2,
             render_pipeline,
+            palette,
+            display_buffer,
+            target_format,
 
             use_1_as_source: true,
+        })
+    }
+
+    // the new parameters are uploaded with the next frame
+    pub fn set_params(&mut self, params: &SimParams) {
+        self.params = *params;
+    }
+
+    pub fn params(&self) -> &SimParams {
+        &self.params
+    }
+
+    pub fn set_palette(&mut self, queue: &Queue, palette: Palette) {
+        self.palette = palette;
+        queue.write_buffer(
+            &self.display_buffer,
+            0,
+            bytemuck::bytes_of(&DisplayUniform::new(palette)),
+        );
+    }
+
+    // feed/kill/du/dv are read per pixel from the map from now on
+    pub fn set_param_map(&mut self, queue: &Queue, map: &ParamMap) -> Result<(), String> {
+        if map.width != self.width || map.height != self.height {
+            return Err(format!(
+                "Parameter map is {}x{} but the grid is {}x{}",
+                map.width, map.height, self.width, self.height
+            ));
         }
+
+        write_f32s(queue, &self.param_texture, &map.data);
+        self.use_param_map = true;
+        (self.param_map_diffusion, self.param_map_uses_global) = map.diffusion();
+        Ok(())
+    }
+
+    // feed goes from feed.0 at the bottom to feed.1 at the top of the window
+    // kill goes from kill.0 on the left to kill.1 on the right
+    pub fn set_param_gradient(&mut self, queue: &Queue, feed: (f32, f32), kill: (f32, f32)) {
+        let map = ParamMap::linear_gradient(self.width, self.height, feed, kill);
+        // the map is created with the grid size so this can not fail
+        let _ = self.set_param_map(queue, &map);
+    }
+
+    // red channel of the image is mapped to the feed range and green to the kill range
+    pub fn load_param_map(
+        &mut self,
+        queue: &Queue,
+        path: &Path,
+        feed: (f32, f32),
+        kill: (f32, f32),
+    ) -> Result<(), String> {
+        let map = ParamMap::from_png(path, self.width, self.height, feed, kill)?;
+        self.set_param_map(queue, &map)
+    }
+
+    // back to the global params
+    pub fn clear_param_map(&mut self) {
+        self.use_param_map = false;
     }
 
-    // resposible for updating time and render pass / compute pass
-    pub fn compute_and_render_pass(&mut self, gpu_res: &GpuResource, frame: &mut FrameContext) {
-        // update dt
-        //let now = self.start_instant.elapsed().as_secs_f32();
-        let dt = 0.5; //(now - self.last_time).max(0.0);
-        //self.last_time = now;
+    // the fastest diffusion anywhere on the grid
+    pub fn max_diffusion(&self) -> f32 {
+        let global = self.params.du.max(self.params.dv);
+        match (self.use_param_map, self.param_map_uses_global) {
+            (true, true) => self.param_map_diffusion.max(global),
+            (true, false) => self.param_map_diffusion,
+            (false, _) => global,
+        }
+    }
 
-        let time_uniform = TimeUniform { dt, _pad: [0.0; 3] };
+    // masked cells are walls from the next step on
+    pub fn set_mask(&mut self, queue: &Queue, mask: &Mask) -> Result<(), String> {
+        if mask.width != self.width || mask.height != self.height {
+            return Err(format!(
+                "Mask is {}x{} but the grid is {}x{}",
+                mask.width, mask.height, self.width, self.height
+            ));
+        }
+        write_f32s(queue, &self.mask_texture, &mask.data);
+        Ok(())
+    }
 
-        gpu_res
-            .queue
-            .write_buffer(&self.time_buffer, 0, bytemuck::bytes_of(&time_uniform));
+    // removes all walls, also the painted ones
+    pub fn clear_mask(&mut self, queue: &Queue) {
+        let mask = Mask::empty(self.width, self.height);
+        write_f32s(queue, &self.mask_texture, &mask.data);
+    }
+
+    // the texture holding the latest state of the field
+    pub fn current_texture(&self) -> &Texture {
+        if self.use_1_as_source {
+            &self.texture_source_1
+        } else {
+            &self.texture_source_2
+        }
+    }
+
+    // overwrites both ping-pong textures with a full RGBA32Float field
+    pub fn upload_field(&self, queue: &Queue, data: &[f32]) {
+        write_f32s(queue, &self.texture_source_1, data);
+        write_f32s(queue, &self.texture_source_2, data);
+    }
+
+    // one simulation step: update params, compute pass and swap the sources
+    // every stage of the integrator is a dispatch, the stage textures take turns
+    // so the one before the last stage always writes stage texture 1
+    pub fn compute_pass(&mut self, queue: &Queue, encoder: &mut CommandEncoder) {
+        // update params
+        let params_uniform = ParamsUniform::new(&self.params, self.use_param_map, self.boundary);
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params_uniform));
 
         // ping or pong?
-        let (compute_bg, render_bg) = if self.use_1_as_source {
-            (&self.compute_bg_1_to_2, &self.render_bg_from_2)
+        let (compute_bg, stage_bgs) = if self.use_1_as_source {
+            (&self.compute_bg_1_to_2, &self.compute_bg_stages_from_1)
         } else {
-            (&self.compute_bg_2_to_1, &self.render_bg_from_1)
+            (&self.compute_bg_2_to_1, &self.compute_bg_stages_from_2)
         };
 
         // compute pass scope
         {
-            let mut cpass = frame.encoder.begin_compute_pass(&ComputePassDescriptor {
+            let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
                 label: Some("Compute Pass"),
                 timestamp_writes: None,
             });
 
-            cpass.set_pipeline(&self.compute_pipeline);
-            cpass.set_bind_group(0, compute_bg, &[]);
-
-            let workgroup_x = (WIDTH + WG_X - 1) / WG_X;
-            let workgroup_y = (HEIGHT + WG_Y - 1) / WG_Y;
-            cpass.dispatch_workgroups(workgroup_x, workgroup_y, 1);
+            let workgroup_x = self.width.div_ceil(self.workgroup_size[0]);
+            let workgroup_y = self.height.div_ceil(self.workgroup_size[1]);
+            let stages = self.compute_pipelines.len();
+            for (i, pipeline) in self.compute_pipelines.iter().enumerate() {
+                let bind_group = if i == stages - 1 {
+                    compute_bg
+                } else {
+                    &stage_bgs[(stages - 2 - i) % 2]
+                };
+                cpass.set_pipeline(pipeline);
+                cpass.set_bind_group(0, bind_group, &[]);
+                cpass.dispatch_workgroups(workgroup_x, workgroup_y, 1);
+            }
         }
 
-        // render pass scope
-        {
-            let mut rpass = frame.encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &frame.view,
-                    resolve_target: None,
-                    ops: Operations {
-                        load: LoadOp::Clear(Color::BLACK),
-                        store: StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
+        self.use_1_as_source = !self.use_1_as_source;
+    }
 
-            rpass.set_pipeline(&self.render_pipeline);
-            rpass.set_bind_group(0, render_bg, &[]);
-            rpass.draw(0..3, 0..1);
-        }
+    // paints the stroke into the current source, the sources are not swapped
+    // the brush uniform is written through the queue so only one stroke per submission
+    pub fn brush_pass(&self, queue: &Queue, encoder: &mut CommandEncoder, stroke: &BrushStroke) {
+        let Some((origin, size)) = stroke.bounds(self.width, self.height) else {
+            return;
+        };
+        let uniform = BrushUniform::new(stroke, origin, size);
+        queue.write_buffer(&self.brush.params_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let (current, other, brush_bg) = if self.use_1_as_source {
+            (
+                &self.texture_source_1,
+                &self.texture_source_2,
+                &self.brush.bg_into_1,
+            )
+        } else {
+            (
+                &self.texture_source_2,
+                &self.texture_source_1,
+                &self.brush.bg_into_2,
+            )
+        };
 
-        self.use_1_as_source = !self.use_1_as_source;
+        // the other texture is overwritten by the next compute pass anyway
+        let region = |texture| TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: Origin3d {
+                x: origin[0],
+                y: origin[1],
+                z: 0,
+            },
+            aspect: TextureAspect::All,
+        };
+        encoder.copy_texture_to_texture(
+            region(current),
+            region(other),
+            Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Brush Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(&self.brush.pipeline);
+        cpass.set_bind_group(0, brush_bg, &[]);
+        cpass.dispatch_workgroups(size[0].div_ceil(WG_X), size[1].div_ceil(WG_Y), 1);
+    }
+
+    // draws the current source into any view with target_format
+    pub fn render_pass(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        let mut rpass = begin_render_pass(encoder, view);
+        self.draw(&mut rpass);
+    }
+
+    // the full-screen triangle into a pass that is already running,
+    // so overlays can be drawn after it in the same pass
+    pub fn draw(&self, rpass: &mut RenderPass) {
+        let render_bg = if self.use_1_as_source {
+            &self.render_bg_from_1
+        } else {
+            &self.render_bg_from_2
+        };
+
+        rpass.set_pipeline(&self.render_pipeline);
+        rpass.set_bind_group(0, render_bg, &[]);
+        rpass.draw(0..3, 0..1);
     }
 
     // reload and rebuild pipelines if shaders are changed
     // TODO This makes this script too long. Should I refactor it or make a script for it?
-    fn reload_compute_pipeline(&mut self, gpu_res: &GpuResource) {
-        let compute_shader_path = load_ablsolute_path("shaders/rd_compute.wgsl");
-        let compute_shader = gpu_res.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Compute Shader (Rebuilding)"),
-            source: ShaderSource::Wgsl(compute_shader_path.into()),
-        });
+    // a shader that does not compile leaves the old pipeline in place
+    fn reload_compute_pipeline(&mut self, device: &Device) -> Result<(), ShaderError> {
+        let compute_bgl = &self.compute_bgl;
+        let workgroup_size = self.workgroup_size;
+        let integrator = self.integrator;
+        let detect_divergence = self.detect_divergence;
+        let pipelines = shader_check::load_checked(
+            device,
+            PipelineKind::Compute.shader_path(),
+            &self.shader_defines(PipelineKind::Compute),
+            |source| {
+                // "new layout" it is the same same but different (after changes in the shader)
+                let compute_pipeline_layout =
+                    device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                        label: Some("Compute Pipeline Layout (Rebuilding"),
+                        bind_group_layouts: &[compute_bgl],
+                        push_constant_ranges: &[],
+                    });
+
+                create_compute_pipelines(
+                    device,
+                    &source,
+                    &compute_pipeline_layout,
+                    workgroup_size,
+                    integrator,
+                    detect_divergence,
+                )
+            },
+        )?;
+        self.compute_pipelines = pipelines;
+        Ok(())
+    }
 
-        // "new layout" it is the same same but different (after changes in the shader)
-        let compute_pipeline_layout =
-            gpu_res
-                .device
-                .create_pipeline_layout(&PipelineLayoutDescriptor {
-                    label: Some("Compute Pipeline Layout (Rebuilding"),
-                    bind_group_layouts: &[&self.compute_bgl],
-                    push_constant_ranges: &[],
+    fn reload_render_pipeline(&mut self, device: &Device) -> Result<(), ShaderError> {
+        let render_bgl = &self.render_bgl;
+        let target_format = self.target_format;
+        let pipeline = shader_check::load_checked(
+            device,
+            PipelineKind::Render.shader_path(),
+            &self.shader_defines(PipelineKind::Render),
+            |source| {
+                let render_shader = device.create_shader_module(ShaderModuleDescriptor {
+                    label: Some("Render Shader (Rebuilding)"),
+                    source: ShaderSource::Wgsl(source.into()),
                 });
 
-        self.compute_pipeline =
-            gpu_res
-                .device
-                .create_compute_pipeline(&ComputePipelineDescriptor {
-                    label: Some("Compute Pipeline (Rebuilding)"),
-                    layout: Some(&compute_pipeline_layout),
-                    module: &compute_shader,
-                    entry_point: Some("main"),
-                    compilation_options: PipelineCompilationOptions::default(),
+                let render_pipeline_layout =
+                    device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                        label: Some("Render Pipeline Layout (Rebuilding)"),
+                        bind_group_layouts: &[render_bgl],
+                        push_constant_ranges: &[],
+                    });
+
+                device.create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some("Render Pipeline (Rebuilding)"),
+                    layout: Some(&render_pipeline_layout),
+                    vertex: VertexState {
+                        module: &render_shader,
+                        entry_point: Some("vs_main"),
+                        compilation_options: PipelineCompilationOptions::default(),
+                        buffers: &[],
+                    },
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    fragment: Some(FragmentState {
+                        module: &render_shader,
+                        entry_point: Some("fs_main"),
+                        compilation_options: PipelineCompilationOptions::default(),
+                        targets: &[Some(ColorTargetState {
+                            format: target_format,
+                            blend: Some(BlendState::REPLACE),
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview: None,
                     cache: None,
-                });
+                })
+            },
+        )?;
+        self.render_pipeline = pipeline;
+        Ok(())
     }
 
-    fn reload_render_pipeline(&mut self, gpu_res: &GpuResource) {
-        let render_shader_path = load_ablsolute_path("shaders/rd_display.wgsl");
-        let render_shader = gpu_res.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Render Shader (Rebuilding)"),
-            source: ShaderSource::Wgsl(render_shader_path.into()),
-        });
+    // the workgroup size is baked into the compute pipeline so it is rebuilt
+    pub fn set_workgroup_size(&mut self, device: &Device, size: [u32; 2]) -> Result<(), String> {
+        validate_workgroup_size(size)?;
+        if size != self.workgroup_size {
+            let old_size = std::mem::replace(&mut self.workgroup_size, size);
+            if let Err(e) = self.reload_compute_pipeline(device) {
+                self.workgroup_size = old_size;
+                return Err(e.to_string());
+            }
+        }
+        Ok(())
+    }
 
-        let render_pipeline_layout =
-            gpu_res
-                .device
-                .create_pipeline_layout(&PipelineLayoutDescriptor {
-                    label: Some("Render Pipeline Layout (Rebuilding)"),
-                    bind_group_layouts: &[&self.render_bgl],
-                    push_constant_ranges: &[],
-                });
+    // a new laplacian means a new compute pipeline, the old one stays if that fails
+    pub fn set_stencil(&mut self, device: &Device, stencil: Stencil) -> Result<(), String> {
+        if stencil != self.stencil {
+            let old_stencil = std::mem::replace(&mut self.stencil, stencil);
+            if let Err(e) = self.reload_compute_pipeline(device) {
+                self.stencil = old_stencil;
+                return Err(e.to_string());
+            }
+        }
+        Ok(())
+    }
 
-        self.render_pipeline = gpu_res
-            .device
-            .create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("Render Pipeline (Rebuilding)"),
-                layout: Some(&render_pipeline_layout),
-                vertex: VertexState {
-                    module: &render_shader,
-                    entry_point: Some("vs_main"),
-                    compilation_options: PipelineCompilationOptions::default(),
-                    buffers: &[],
-                },
-                primitive: PrimitiveState::default(),
-                depth_stencil: None,
-                multisample: MultisampleState::default(),
-                fragment: Some(FragmentState {
-                    module: &render_shader,
-                    entry_point: Some("fs_main"),
-                    compilation_options: PipelineCompilationOptions::default(),
-                    targets: &[Some(ColorTargetState {
-                        format: gpu_res.surface_format(),
-                        blend: Some(BlendState::REPLACE),
-                        write_mask: ColorWrites::ALL,
-                    })],
-                }),
-                multiview: None,
-                cache: None,
-            });
+    // every stage is its own compute pipeline, the old ones stay if building fails
+    pub fn set_integrator(
+        &mut self,
+        device: &Device,
+        integrator: Integrator,
+    ) -> Result<(), String> {
+        if integrator != self.integrator {
+            let old_integrator = std::mem::replace(&mut self.integrator, integrator);
+            if let Err(e) = self.reload_compute_pipeline(device) {
+                self.integrator = old_integrator;
+                return Err(e.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // the check is an `override` of the compute shader, so the pipelines are rebuilt
+    pub fn set_detect_divergence(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        detect: bool,
+    ) -> Result<(), String> {
+        if detect != self.detect_divergence {
+            self.detect_divergence = detect;
+            if let Err(e) = self.reload_compute_pipeline(device) {
+                self.detect_divergence = !detect;
+                return Err(e.to_string());
+            }
+            self.clear_divergence(queue);
+        }
+        Ok(())
+    }
+
+    pub fn clear_divergence(&self, queue: &Queue) {
+        queue.write_buffer(
+            &self.divergence_buffer,
+            0,
+            bytemuck::bytes_of(&DivergenceFlags::CLEAR),
+        );
+    }
+
+    // the #defines a pipeline's shader is preprocessed with
+    pub fn shader_defines(&self, kind: PipelineKind) -> Vec<(&'static str, String)> {
+        match kind {
+            PipelineKind::Compute => self.stencil.shader_defines(),
+            PipelineKind::Render => Vec::new(),
+            PipelineKind::Brush => brush::shader_defines(),
+        }
+    }
+
+    // the pipelines whose shader is `path` or #includes it
+    pub fn pipelines_using(&self, path: &Path) -> Vec<PipelineKind> {
+        PipelineKind::ALL
+            .into_iter()
+            .filter(|&kind| {
+                let shader = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(kind.shader_path());
+                preprocessor::included_files(&shader, &self.shader_defines(kind))
+                    .iter()
+                    .any(|file| file == path)
+            })
+            .collect()
+    }
+
+    // rebuild one pipeline from its shader, on failure the last good version keeps running
+    pub fn rebuild(&mut self, device: &Device, kind: PipelineKind) -> Result<(), ShaderError> {
+        println!("Rebuilding {:?} Pipeline (Hot Reload)", kind);
+        match kind {
+            PipelineKind::Compute => self.reload_compute_pipeline(device),
+            PipelineKind::Render => self.reload_render_pipeline(device),
+            PipelineKind::Brush => self.brush.reload_pipeline(device),
+        }
     }
 
-    // rebuild
-    pub fn rebuild_pipeline(&mut self, gpu_res: &GpuResource) {
+    // rebuild all of them
+    pub fn rebuild_pipeline(&mut self, device: &Device) -> Result<(), Vec<ShaderError>> {
         println!("Rebuilding Pipelines (Hot Reload)");
-        self.reload_compute_pipeline(gpu_res);
-        self.reload_render_pipeline(gpu_res);
+        let errors: Vec<ShaderError> = PipelineKind::ALL
+            .into_iter()
+            .filter_map(|kind| self.rebuild(device, kind).err())
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
         println!("Pipelines Fully Reloaded (Hot Reload)");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stencil_weights_sum_to_zero() {
+        for stencil in Stencil::ALL {
+            let w = stencil.weights();
+            let sum = w.center + 4.0 * (w.edge + w.corner + w.far);
+            assert!(sum.abs() < 1e-6, "{} sums to {}", stencil.name(), sum);
+        }
+    }
+
+    #[test]
+    fn max_eigenvalue_is_the_checkerboard() {
+        // every stencil decays fastest at kx = ky = pi, where the symbol is
+        // center - 4 edge + 4 corner + 4 far
+        for stencil in Stencil::ALL {
+            let w = stencil.weights();
+            let checkerboard = -(w.center - 4.0 * w.edge + 4.0 * w.corner + 4.0 * w.far);
+            let max = stencil.max_eigenvalue();
+            assert!(
+                (max - checkerboard).abs() < 1e-5,
+                "{}: {} vs {}",
+                stencil.name(),
+                max,
+                checkerboard
+            );
+        }
+        assert!((Stencil::FivePoint.max_eigenvalue() - 8.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn jacobi_only_limits_fourth_order() {
+        for stencil in Stencil::ALL {
+            match stencil {
+                Stencil::FourthOrder => {
+                    let limit = stencil.jacobi_limit().unwrap();
+                    assert!((limit - 1.5).abs() < 1e-5, "{}", limit);
+                }
+                _ => assert_eq!(stencil.jacobi_limit(), None, "{}", stencil.name()),
+            }
+        }
+    }
+
+    #[test]
+    fn runge_kutta_weights_sum_to_one() {
+        for (integrator, count) in [
+            (Integrator::Euler, 1),
+            (Integrator::Heun, 2),
+            (Integrator::Rk4, 4),
+        ] {
+            let stages = integrator.stages();
+            assert_eq!(stages.len(), count, "{}", integrator);
+            let sum: f32 = stages.iter().map(|stage| stage.weight).sum();
+            assert!((sum - 1.0).abs() < 1e-6, "{} sums to {}", integrator, sum);
+            assert!(stages.iter().all(|s| s.kind == StageKind::RungeKutta));
+        }
+    }
+
+    #[test]
+    fn only_the_last_stage_writes_the_field() {
+        let integrators = [
+            Integrator::Euler,
+            Integrator::Heun,
+            Integrator::Rk4,
+            Integrator::Imex { iterations: 5 },
+        ];
+        for integrator in integrators {
+            let stages = integrator.stages();
+            assert!(stages[0].first, "{}", integrator);
+            assert!(stages[1..].iter().all(|s| !s.first), "{}", integrator);
+            assert!(stages.last().unwrap().last, "{}", integrator);
+            let writes_field = stages.iter().filter(|s| s.last).count();
+            assert_eq!(writes_field, 1, "{}", integrator);
+        }
+    }
+
+    #[test]
+    fn imex_is_the_reaction_then_the_iterations() {
+        let stages = Integrator::Imex { iterations: 5 }.stages();
+        assert_eq!(stages.len(), 6);
+        assert_eq!(stages[0].kind, StageKind::ImexReaction);
+        assert!(stages[1..].iter().all(|s| s.kind == StageKind::ImexJacobi));
+        // at least one iteration, even when asked for none
+        assert_eq!(Integrator::Imex { iterations: 0 }.stages().len(), 2);
+    }
+
+    #[test]
+    fn integrator_display_parses_back() {
+        let integrators = [
+            Integrator::Euler,
+            Integrator::Heun,
+            Integrator::Rk4,
+            Integrator::Imex { iterations: 7 },
+        ];
+        for integrator in integrators {
+            assert_eq!(Integrator::parse(&integrator.to_string()), Ok(integrator));
+        }
     }
 }
@@ -0,0 +1,160 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use wgpu::*;
+
+// copies a whole texture into a mappable buffer and waits for it
+// returns tightly packed rows (the copy itself needs rows padded to 256 bytes)
+pub fn read_texture(device: &Device, queue: &Queue, texture: &Texture) -> Result<Vec<u8>, String> {
+    let width = texture.width();
+    let height = texture.height();
+    let bytes_per_pixel = texture
+        .format()
+        .block_copy_size(None)
+        .ok_or_else(|| format!("Texture format {:?} can not be copied", texture.format()))?;
+
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size: (padded_bytes_per_row * height) as u64,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        TexelCopyBufferInfo {
+            buffer: &staging,
+            layout: TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit([encoder.finish()]);
+
+    let slice = staging.slice(..);
+    wait_for_map(device, &slice)?;
+
+    // strip the row padding
+    let mut data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+    {
+        let mapped = slice.get_mapped_range();
+        for row in mapped.chunks_exact(padded_bytes_per_row as usize) {
+            data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+    staging.unmap();
+
+    Ok(data)
+}
+
+// copies a whole buffer (it needs COPY_SRC) and waits for it
+pub fn read_buffer(device: &Device, queue: &Queue, buffer: &Buffer) -> Result<Vec<u8>, String> {
+    let staging = device.create_buffer(&BufferDescriptor {
+        label: Some("Readback Staging Buffer"),
+        size: buffer.size(),
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+    queue.submit([encoder.finish()]);
+
+    let slice = staging.slice(..);
+    wait_for_map(device, &slice)?;
+    let data = slice.get_mapped_range().to_vec();
+    staging.unmap();
+
+    Ok(data)
+}
+
+// a buffer copy that is mapped in the background, for reads that should not stall a frame
+// the staging buffer lives until the copy was read or dropped
+pub struct PendingReadback {
+    staging: Buffer,
+    reciever_x: Receiver<Result<(), BufferAsyncError>>,
+}
+
+impl PendingReadback {
+    // copies a whole buffer (it needs COPY_SRC) after the work submitted so far
+    pub fn start(device: &Device, queue: &Queue, buffer: &Buffer) -> Self {
+        let staging = device.create_buffer(&BufferDescriptor {
+            label: Some("Async Readback Staging Buffer"),
+            size: buffer.size(),
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Async Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        queue.submit([encoder.finish()]);
+
+        let (sender_x, reciever_x) = mpsc::channel();
+        staging.slice(..).map_async(MapMode::Read, move |res| {
+            let _ = sender_x.send(res);
+        });
+        Self {
+            staging,
+            reciever_x,
+        }
+    }
+
+    // the copied bytes once the GPU got there, None while it is still busy
+    pub fn try_read(&self, device: &Device) -> Option<Result<Vec<u8>, String>> {
+        // runs the map callback if the copy is done, does not wait for it
+        if let Err(e) = device.poll(PollType::Poll) {
+            return Some(Err(format!("Failed to poll the GPU: {}", e)));
+        }
+        let mapped = match self.reciever_x.try_recv() {
+            Ok(mapped) => mapped,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => {
+                return Some(Err("Readback was dropped".to_string()));
+            }
+        };
+        if let Err(e) = mapped {
+            return Some(Err(format!("Failed to map the readback buffer: {}", e)));
+        }
+
+        let data = self.staging.slice(..).get_mapped_range().to_vec();
+        self.staging.unmap();
+        Some(Ok(data))
+    }
+}
+
+// block until the GPU is done and the buffer is mapped
+fn wait_for_map(device: &Device, slice: &BufferSlice) -> Result<(), String> {
+    let (sender_x, reciever_x) = mpsc::channel();
+    slice.map_async(MapMode::Read, move |res| {
+        let _ = sender_x.send(res);
+    });
+    device
+        .poll(PollType::Wait)
+        .map_err(|e| format!("Failed to wait for the GPU: {}", e))?;
+    reciever_x
+        .recv()
+        .map_err(|e| format!("Readback was dropped: {}", e))?
+        .map_err(|e| format!("Failed to map the readback buffer: {}", e))
+}
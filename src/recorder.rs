@@ -0,0 +1,149 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::image_io::{self, PngText, RgbaImage};
+
+// what a recording writes
+pub struct RecorderConfig {
+    // keep every Nth frame
+    pub every: u32,
+    // numbered PNG sequence frame_00000.png, frame_00001.png, ...
+    pub png_dir: Option<PathBuf>,
+    // animated GIF
+    pub gif_path: Option<PathBuf>,
+    // display time of one GIF frame in 1/100 s
+    pub gif_delay: u16,
+}
+
+impl RecorderConfig {
+    // PNG sequence and recording.gif in the same directory
+    pub fn in_dir(dir: impl Into<PathBuf>, every: u32) -> Self {
+        let dir = dir.into();
+        Self {
+            every,
+            gif_path: Some(dir.join("recording.gif")),
+            png_dir: Some(dir),
+            gif_delay: 4,
+        }
+    }
+}
+
+// captures every Nth frame into a PNG sequence and/or an animated GIF
+pub struct Recorder {
+    config: RecorderConfig,
+    frames_seen: u64,
+    frames_saved: u32,
+    // size of the first frame, every later one has to match it
+    size: Option<(u32, u32)>,
+    // created with the first frame since it needs the frame size
+    gif: Option<Encoder<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Result<Self, String> {
+        if config.every == 0 {
+            return Err("Recorder needs to keep at least every 1st frame".to_string());
+        }
+        if let Some(dir) = &config.png_dir {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+
+        Ok(Self {
+            config,
+            frames_seen: 0,
+            frames_saved: 0,
+            size: None,
+            gif: None,
+        })
+    }
+
+    // call once per frame, true if this frame should be captured
+    pub fn wants_frame(&mut self) -> bool {
+        let wanted = self.frames_seen.is_multiple_of(self.config.every as u64);
+        self.frames_seen += 1;
+        wanted
+    }
+
+    pub fn frames_saved(&self) -> u32 {
+        self.frames_saved
+    }
+
+    // a frame of another size (the window was resized) is an error, the GIF can not change size
+    pub fn add_frame(&mut self, image: &RgbaImage, text: &PngText) -> Result<(), String> {
+        let size = (image.width, image.height);
+        if let Some(first) = self.size
+            && first != size
+        {
+            return Err(format!(
+                "Frame size changed during recording from {}x{} to {}x{}",
+                first.0, first.1, size.0, size.1
+            ));
+        }
+        self.size = Some(size);
+
+        if let Some(dir) = &self.config.png_dir {
+            let path = dir.join(format!("frame_{:05}.png", self.frames_saved));
+            image_io::save_png(&path, image, text)?;
+        }
+
+        if let Some(gif_path) = &self.config.gif_path {
+            if self.gif.is_none() {
+                self.gif = Some(create_gif(gif_path, image)?);
+            }
+            let encoder = self.gif.as_mut().expect("GIF encoder was just created");
+
+            // the palette quantization (NeuQuant) changes the pixels so it needs a copy
+            let mut pixels = image.pixels.clone();
+            // speed 10 is the recommended trade-off between quality and time
+            let mut frame =
+                Frame::from_rgba_speed(image.width as u16, image.height as u16, &mut pixels, 10);
+            frame.delay = self.config.gif_delay;
+            encoder
+                .write_frame(&frame)
+                .map_err(|e| format!("Failed to write {:?}: {}", gif_path, e))?;
+        }
+
+        self.frames_saved += 1;
+        Ok(())
+    }
+
+    // closes the GIF, returns the number of saved frames
+    pub fn finish(self) -> Result<u32, String> {
+        if let Some(encoder) = self.gif {
+            let mut writer = encoder
+                .into_inner()
+                .map_err(|e| format!("Failed to finish the GIF: {}", e))?;
+            writer
+                .flush()
+                .map_err(|e| format!("Failed to finish the GIF: {}", e))?;
+        }
+        Ok(self.frames_saved)
+    }
+}
+
+fn create_gif(path: &Path, image: &RgbaImage) -> Result<Encoder<BufWriter<File>>, String> {
+    if image.width > u16::MAX as u32 || image.height > u16::MAX as u32 {
+        return Err(format!(
+            "GIF frames can be at most 65535x65535, got {}x{}",
+            image.width, image.height
+        ));
+    }
+
+    let file = File::create(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    let mut encoder = Encoder::new(
+        BufWriter::new(file),
+        image.width as u16,
+        image.height as u16,
+        &[],
+    )
+    .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    Ok(encoder)
+}
@@ -0,0 +1,452 @@
+// Scene files describe a complete simulation setup in TOML
+//
+//   seed = 0
+//
+//   [grid]
+//   width = 1280
+//   height = 1280
+//   workgroup_size = [16, 16]
+//   stencil = "five-point"   # isotropic, oono-puri, sims, fourth-order
+//
+//   [params]
+//   du = 0.19
+//   dv = 0.08
+//   feed = 0.0345
+//   kill = 0.062
+//   dt = 0.5
+//
+//   [init]
+//   type = "blob"
+//   radius = 10
+//
+//   [boundary]
+//   type = "dirichlet"    # or "neumann" (default) and "periodic" without values
+//   u = 1.0
+//   v = 0.0
+//
+//   [integrator]
+//   type = "imex"         # or "euler" (default), "heun" and "rk4" without values
+//   iterations = 20       # Jacobi iterations per step
+//
+//   [stability]
+//   auto_dt = false             # true: clamp dt below the stable limit instead of warning
+//   detect_divergence = false   # true: pause when the field turns NaN or leaves [0, 1]
+//
+//   [mask]                # no walls without it
+//   type = "text"         # or "circle" and "image" with a path
+//   text = "HI"
+//   invert = false        # true: walls inside the shape instead of around it
+//
+//   [param_map]           # the global feed and kill everywhere without it
+//   type = "gradient"     # or "image" with a path (red = feed, green = kill)
+//   feed = [0.01, 0.1]    # bottom to top
+//   kill = [0.045, 0.07]  # left to right
+//
+//   [display]
+//   palette = "cosine"
+//   present_mode = "fifo"
+//   steps_per_frame = 1
+//
+//   [brush]
+//   radius = 12.0
+//   strength = 1.0
+//   falloff = 0.5
+//
+// every section and every key is optional, missing ones keep their defaults
+
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{
+    DEFAULT_HEIGHT, DEFAULT_WIDTH,
+    brush::BrushSettings,
+    clock::MAX_STEPS_PER_FRAME,
+    initial_conditions::InitialCondition,
+    mask::MaskShape,
+    param_map::ParamMapSource,
+    rd_system::{self, Boundary, Integrator, Palette, SimParams, Stencil, WG_X, WG_Y},
+    simulation::Simulation,
+    stability::StabilitySettings,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub seed: u64,
+    pub grid: GridSettings,
+    pub params: SimParams,
+    pub init: InitialCondition,
+    pub boundary: Boundary,
+    pub integrator: Integrator,
+    pub stability: StabilitySettings,
+    pub mask: Option<MaskShape>,
+    pub param_map: Option<ParamMapSource>,
+    pub display: DisplaySettings,
+    pub brush: BrushSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GridSettings {
+    pub width: u32,
+    pub height: u32,
+    pub workgroup_size: [u32; 2],
+    pub stencil: Stencil,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            workgroup_size: [WG_X, WG_Y],
+            stencil: Stencil::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplaySettings {
+    pub palette: Palette,
+    pub present_mode: PresentMode,
+    pub steps_per_frame: u32,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            palette: Palette::default(),
+            present_mode: PresentMode::default(),
+            steps_per_frame: 1,
+        }
+    }
+}
+
+// the wgpu present modes under the names used in scene files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentMode {
+    #[default]
+    Fifo,
+    Mailbox,
+    Immediate,
+    AutoVsync,
+    AutoNoVsync,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => Self::Fifo,
+            PresentMode::Mailbox => Self::Mailbox,
+            PresentMode::Immediate => Self::Immediate,
+            PresentMode::AutoVsync => Self::AutoVsync,
+            PresentMode::AutoNoVsync => Self::AutoNoVsync,
+        }
+    }
+}
+
+impl Scene {
+    // grid, params, boundary, integrator, stability and palette of a running simulation, everything else is default
+    pub fn from_simulation(simulation: &Simulation, init: &InitialCondition, seed: u64) -> Self {
+        Self {
+            seed,
+            grid: GridSettings {
+                width: simulation.width(),
+                height: simulation.height(),
+                workgroup_size: simulation.rd_system.workgroup_size,
+                stencil: simulation.stencil(),
+            },
+            params: *simulation.params(),
+            init: init.clone(),
+            boundary: simulation.boundary(),
+            integrator: simulation.integrator(),
+            stability: StabilitySettings {
+                auto_dt: simulation.auto_dt(),
+                detect_divergence: simulation.detect_divergence(),
+            },
+            // the simulation only knows the walls, not where they came from
+            mask: None,
+            // the simulation only knows the parameter map, not where it came from
+            param_map: None,
+            display: DisplaySettings {
+                palette: simulation.palette(),
+                ..Default::default()
+            },
+            brush: BrushSettings::default(),
+        }
+    }
+
+    // relative image paths in [init], [mask] and [param_map] are relative to the scene file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        let mut scene =
+            Self::from_toml(&text).map_err(|e| format!("Invalid scene {:?}: {}", path, e))?;
+
+        let images = [
+            match &mut scene.init {
+                InitialCondition::Image { path, .. } => Some(path),
+                _ => None,
+            },
+            match &mut scene.mask {
+                Some(MaskShape::Image { path, .. }) => Some(path),
+                _ => None,
+            },
+            match &mut scene.param_map {
+                Some(ParamMapSource::Image { path, .. }) => Some(path),
+                _ => None,
+            },
+        ];
+        for image in images.into_iter().flatten() {
+            if image.is_relative()
+                && let Some(dir) = path.parent()
+            {
+                *image = dir.join(&*image);
+            }
+        }
+        Ok(scene)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let scene: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| format!("Failed to write the scene: {}", e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_toml()?).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+
+    // value checks serde can not do, the errors name the key
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, value) in [
+            ("grid.width", self.grid.width),
+            ("grid.height", self.grid.height),
+        ] {
+            if !(1..=rd_system::MAX_GRID_DIMENSION).contains(&value) {
+                return Err(format!(
+                    "{} must be between 1 and {}, got {}",
+                    key,
+                    rd_system::MAX_GRID_DIMENSION,
+                    value
+                ));
+            }
+        }
+        rd_system::validate_workgroup_size(self.grid.workgroup_size)
+            .map_err(|e| format!("grid.workgroup_size: {}", e))?;
+
+        let params = &self.params;
+        for (key, value) in [
+            ("params.du", params.du),
+            ("params.dv", params.dv),
+            ("params.feed", params.feed),
+            ("params.kill", params.kill),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(format!("{} must be a number >= 0, got {}", key, value));
+            }
+        }
+        if !params.dt.is_finite() || params.dt <= 0.0 {
+            return Err(format!("params.dt must be a number > 0, got {}", params.dt));
+        }
+
+        self.init
+            .validate(self.grid.width, self.grid.height)
+            .map_err(|e| format!("init.{}", e))?;
+
+        if let Boundary::Dirichlet { u, v } = self.boundary {
+            for (key, value) in [("boundary.u", u), ("boundary.v", v)] {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(format!("{} must be between 0 and 1, got {}", key, value));
+                }
+            }
+        }
+
+        if let Some(param_map) = &self.param_map {
+            param_map
+                .validate()
+                .map_err(|e| format!("param_map.{}", e))?;
+        }
+
+        if let Integrator::Imex { iterations: 0 } = self.integrator {
+            return Err("integrator.iterations must be at least 1".to_string());
+        }
+
+        let steps = self.display.steps_per_frame;
+        if !(1..=MAX_STEPS_PER_FRAME).contains(&steps) {
+            return Err(format!(
+                "display.steps_per_frame must be between 1 and {}, got {}",
+                MAX_STEPS_PER_FRAME, steps
+            ));
+        }
+
+        let brush = &self.brush;
+        if !brush.radius.is_finite() || brush.radius <= 0.0 {
+            return Err(format!(
+                "brush.radius must be a number > 0, got {}",
+                brush.radius
+            ));
+        }
+        for (key, value) in [
+            ("brush.strength", brush.strength),
+            ("brush.falloff", brush.falloff),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be between 0 and 1, got {}", key, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every section with values other than the defaults
+    fn scene() -> Scene {
+        Scene {
+            seed: 42,
+            grid: GridSettings {
+                width: 320,
+                height: 200,
+                workgroup_size: [8, 4],
+                stencil: Stencil::FourthOrder,
+            },
+            params: SimParams {
+                du: 0.2,
+                dv: 0.1,
+                feed: 0.04,
+                kill: 0.06,
+                dt: 0.25,
+            },
+            init: InitialCondition::RandomSpots {
+                count: 12,
+                radius: 5,
+            },
+            boundary: Boundary::Dirichlet { u: 0.9, v: 0.2 },
+            integrator: Integrator::Imex { iterations: 12 },
+            stability: StabilitySettings {
+                auto_dt: true,
+                detect_divergence: true,
+            },
+            mask: Some(MaskShape::Text {
+                text: "RD".to_string(),
+                invert: true,
+            }),
+            param_map: Some(ParamMapSource::Gradient {
+                feed: [0.02, 0.06],
+                kill: [0.05, 0.065],
+            }),
+            display: DisplaySettings {
+                palette: Palette::Ocean,
+                present_mode: PresentMode::AutoNoVsync,
+                steps_per_frame: 8,
+            },
+            brush: BrushSettings {
+                radius: 20.0,
+                strength: 0.5,
+                falloff: 0.25,
+            },
+        }
+    }
+
+    fn toml_error(text: &str) -> String {
+        match Scene::from_toml(text) {
+            Ok(scene) => panic!("{:?} loaded as {:?}", text, scene),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        for scene in [scene(), Scene::default()] {
+            let text = scene.to_toml().unwrap();
+            assert_eq!(Scene::from_toml(&text).unwrap(), scene, "{}", text);
+        }
+    }
+
+    #[test]
+    fn example_in_the_header_comment_loads() {
+        // the example at the top of this file without the // and the sentences around it
+        let example: String = include_str!("scene.rs")
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with("// every section"))
+            .map(|line| line.trim_start_matches("//").trim_start())
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let scene = Scene::from_toml(&example).unwrap();
+
+        assert_eq!(scene.grid.width, 1280);
+        assert_eq!(scene.grid.workgroup_size, [16, 16]);
+        assert_eq!(scene.init, InitialCondition::CenterBlob { radius: 10 });
+        assert_eq!(scene.boundary, Boundary::Dirichlet { u: 1.0, v: 0.0 });
+        assert_eq!(scene.integrator, Integrator::Imex { iterations: 20 });
+        assert_eq!(
+            scene.mask,
+            Some(MaskShape::Text {
+                text: "HI".to_string(),
+                invert: false
+            })
+        );
+        assert_eq!(
+            scene.param_map,
+            Some(ParamMapSource::Gradient {
+                feed: [0.01, 0.1],
+                kill: [0.045, 0.07]
+            })
+        );
+        assert_eq!(scene.brush.radius, 12.0);
+    }
+
+    #[test]
+    fn bad_values_are_rejected_by_key() {
+        // (scene, what the error has to mention)
+        let cases = [
+            ("[grid]\nwidth = 0", "grid.width"),
+            ("[grid]\nheight = 100000", "grid.height"),
+            ("[grid]\nworkgroup_size = [0, 8]", "grid.workgroup_size"),
+            ("[params]\ndu = nan", "params.du"),
+            ("[params]\nfeed = -0.1", "params.feed"),
+            ("[params]\ndt = 0.0", "params.dt"),
+            ("[init]\ntype = \"noise\"\namount = 2.0", "init.amount"),
+            (
+                "[grid]\nwidth = 4\nheight = 4\n[init]\ntype = \"spots\"\ncount = 17\nradius = 1",
+                "init.count",
+            ),
+            (
+                "[boundary]\ntype = \"dirichlet\"\nu = 1.5\nv = 0.0",
+                "boundary.u",
+            ),
+            (
+                "[integrator]\ntype = \"imex\"\niterations = 0",
+                "integrator.iterations",
+            ),
+            (
+                "[param_map]\ntype = \"gradient\"\nkill = [-1.0, 0.1]",
+                "param_map.kill",
+            ),
+            ("[display]\nsteps_per_frame = 0", "display.steps_per_frame"),
+            ("[brush]\nradius = 0.0", "brush.radius"),
+            ("[brush]\nstrength = 2.0", "brush.strength"),
+            ("[brush]\nfalloff = -0.5", "brush.falloff"),
+            // serde: unknown keys, wrong types and unknown names
+            ("[grid]\nwdith = 5", "wdith"),
+            ("[params]\nfeed = \"a lot\"", "feed"),
+            ("[grid]\nstencil = \"nine-point\"", "nine-point"),
+            ("[display]\npalette = \"rainbow\"", "rainbow"),
+            ("[mask]\ntype = \"square\"", "square"),
+        ];
+        for (text, key) in cases {
+            let error = toml_error(text);
+            assert!(error.contains(key), "{:?} gave {:?}", text, error);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// checks hot reloaded shaders before they reach wgpu
+// wgpu's default error handler panics on a broken shader, so a typo in the editor
+// would end the session. naga finds parse and validation errors up front and an
+// error scope catches what only the pipeline creation sees (bindings, entry points)
+
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use wgpu::{Device, ErrorFilter};
+
+use crate::preprocessor::{self, Preprocessed};
+
+// why a shader could not be used, line and column are 1 based
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderError {
+    pub path: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    // the full diagnostic, with the source line and a caret under the error where known
+    pub message: String,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}:{}", self.path.display(), line, column)?,
+            _ => write!(f, "{}", self.path.display())?,
+        }
+        write!(f, "\n{}", self.message.trim_end())
+    }
+}
+
+impl ShaderError {
+    pub(crate) fn without_location(path: PathBuf, message: String) -> Self {
+        Self {
+            path,
+            line: None,
+            column: None,
+            message,
+        }
+    }
+}
+
+// one underlined piece of source in a diagnostic
+pub(crate) struct Label<'a> {
+    pub path: &'a Path,
+    pub line: u32,
+    pub column: u32,
+    pub length: u32,
+    // the whole source line
+    pub text: &'a str,
+    pub message: &'a str,
+}
+
+// the same layout naga uses for its errors, but every label can be in another file
+//
+//   error: expected assignment or increment/decrement, found "dims"
+//      ┌─ shaders/common.wgsl:4:10
+//      │
+//    4 │     lett dims = textureDimensions(texture);
+//      │          ^^^^ expected assignment or increment/decrement
+pub(crate) fn render_diagnostic(message: &str, labels: &[Label], notes: &[String]) -> String {
+    let width = labels
+        .iter()
+        .map(|l| l.line.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let pad = " ".repeat(width + 1);
+
+    let mut out = format!("error: {}\n", message);
+    for label in labels {
+        out += &format!(
+            "{}┌─ {}:{}:{}\n",
+            pad,
+            label.path.display(),
+            label.line,
+            label.column
+        );
+        out += &format!("{}│\n", pad);
+        out += &format!("{:>width$} │ {}\n", label.line, label.text);
+        out += &format!(
+            "{}│ {}{} {}\n",
+            pad,
+            " ".repeat(label.column.saturating_sub(1) as usize),
+            "^".repeat(label.length.max(1) as usize),
+            label.message
+        );
+    }
+    for note in notes {
+        out += &format!("{}= note: {}\n", pad, note);
+    }
+    out
+}
+
+// the preprocessed shader, a missing file (some editors delete before writing) is an error
+pub fn read_shader(
+    relative_path: &str,
+    defines: &[(&str, String)],
+) -> Result<Preprocessed, ShaderError> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative_path);
+    preprocessor::preprocess(&path, defines)
+}
+
+// parses and validates WGSL with naga
+// all capabilities are allowed here, the device checks its own when the pipeline is created
+pub fn validate_wgsl(shader: &Preprocessed) -> Result<(), ShaderError> {
+    // spans are byte ranges of the expanded source, the labels point into the original files
+    let to_error = |message: &str, spans: Vec<(Range<usize>, &str)>, notes: &[String]| {
+        let positions: Vec<_> = spans
+            .iter()
+            .filter_map(|(range, text)| Some((shader.position(range.start)?, range.len(), *text)))
+            .collect();
+        let labels: Vec<Label> = positions
+            .iter()
+            .map(|(position, length, text)| Label {
+                path: position.path,
+                line: position.line,
+                column: position.column,
+                length: *length as u32,
+                text: position.text,
+                message: text,
+            })
+            .collect();
+
+        let first = positions.first();
+        ShaderError {
+            path: first.map_or(shader.files[0].clone(), |(p, _, _)| p.path.to_path_buf()),
+            line: first.map(|(p, _, _)| p.line),
+            column: first.map(|(p, _, _)| p.column),
+            message: render_diagnostic(message, &labels, notes),
+        }
+    };
+
+    let module = naga::front::wgsl::parse_str(&shader.source).map_err(|e| {
+        let spans = e
+            .labels()
+            .filter_map(|(span, text)| Some((span.to_range()?, text)))
+            .collect();
+        to_error(e.message(), spans, &[])
+    })?;
+
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|e| {
+            let spans = e
+                .spans()
+                .filter_map(|(span, text)| Some((span.to_range()?, text.as_str())))
+                .collect();
+            // the causes of a validation error, naga prints them as notes as well
+            let mut notes = Vec::new();
+            let mut source: &dyn Error = e.as_inner();
+            while let Some(next) = source.source() {
+                notes.push(next.to_string());
+                source = next;
+            }
+            to_error(&e.as_inner().to_string(), spans, &notes)
+        })?;
+    Ok(())
+}
+
+// runs `create` inside a validation error scope, so a bad pipeline is an Err instead of a panic
+pub fn create_checked<T>(
+    device: &Device,
+    relative_path: &str,
+    create: impl FnOnce() -> T,
+) -> Result<T, ShaderError> {
+    device.push_error_scope(ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        None => Ok(created),
+        Some(e) => {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(relative_path);
+            Err(ShaderError::without_location(path, e.to_string()))
+        }
+    }
+}
+
+// preprocess, validate and create in one go, the usual way to reload a pipeline
+pub fn load_checked<T>(
+    device: &Device,
+    relative_path: &str,
+    defines: &[(&str, String)],
+    create: impl FnOnce(String) -> T,
+) -> Result<T, ShaderError> {
+    let shader = read_shader(relative_path, defines)?;
+    validate_wgsl(&shader)?;
+    create_checked(device, relative_path, || create(shader.source))
+}
@@ -1,15 +1,21 @@
 use std::{
+    fs,
     path::PathBuf,
     sync::mpsc::{Receiver, channel},
+    time::{Duration, Instant},
 };
 
 use notify::{
     Event, EventKind, RecommendedWatcher, Watcher, event::ModifyKind, recommended_watcher,
 };
 
+// sends the path of every changed file in the watched directories
 pub struct ShaderWatcher {
-    _watcher: RecommendedWatcher,
+    watcher: RecommendedWatcher,
     pub reciever_x: Receiver<PathBuf>,
+    // changed files collected by settled_changes, without duplicates
+    pending: Vec<PathBuf>,
+    last_event: Option<Instant>,
 }
 
 impl ShaderWatcher {
@@ -19,7 +25,10 @@ impl ShaderWatcher {
 
         let mut watcher = recommended_watcher(move |res: Result<Event, notify::Error>| match res {
             Ok(event) => {
-                if let EventKind::Modify(ModifyKind::Data(_)) = event.kind {
+                // renames and creates too, some editors save by replacing the file
+                if let EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
+                | EventKind::Create(_) = event.kind
+                {
                     for p in event.paths {
                         let _ = sender_x.send(p);
                     }
@@ -36,8 +45,44 @@ impl ShaderWatcher {
             .expect("Failed to watch the shader path!");
 
         Self {
-            _watcher: watcher,
+            watcher,
             reciever_x,
+            pending: Vec::new(),
+            last_event: None,
         }
     }
+
+    // debounced reciever_x, an editor save is often several events (truncate, write, rename)
+    // returns every file that changed once no event came in for `quiet`, else nothing
+    pub fn settled_changes(&mut self, quiet: Duration) -> Vec<PathBuf> {
+        while let Ok(path) = self.reciever_x.try_recv() {
+            if !self.pending.contains(&path) {
+                self.pending.push(path);
+            }
+            self.last_event = Some(Instant::now());
+        }
+
+        match self.last_event {
+            Some(last) if last.elapsed() >= quiet => {
+                self.last_event = None;
+                std::mem::take(&mut self.pending)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // also report changes of a single file (e.g. a scene), returns the path as it will be sent
+    // this watches the directory of the file so replacing the file is noticed as well
+    pub fn watch_file(&mut self, file: impl Into<PathBuf>) -> Result<PathBuf, String> {
+        let file = file.into();
+        let file =
+            fs::canonicalize(&file).map_err(|e| format!("Failed to watch {:?}: {}", file, e))?;
+        let dir = file
+            .parent()
+            .ok_or_else(|| format!("Failed to watch {:?}: it has no directory", file))?;
+        self.watcher
+            .watch(dir, notify::RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Failed to watch {:?}: {}", dir, e))?;
+        Ok(file)
+    }
 }
@@ -0,0 +1,558 @@
+use std::path::Path;
+
+use wgpu::*;
+
+use crate::{
+    brush::BrushStroke,
+    checkpoint::Checkpoint,
+    image_io::{self, PngText, RgbaImage},
+    initial_conditions::InitialCondition,
+    mask::Mask,
+    offscreen::OffscreenTarget,
+    param_map::ParamMap,
+    rd_system::{
+        Boundary, DivergenceFlags, Integrator, Palette, PipelineKind, ReactionDiffusionSystem,
+        SimParams, Stencil,
+    },
+    readback,
+    shader_check::ShaderError,
+    stability::{self, Divergence},
+};
+
+// Reaction diffusion simulation that only needs a device and a queue
+// stepping and rendering either go into a caller's encoder (encode_*)
+// or are submitted right away
+pub struct Simulation {
+    pub device: Device,
+    pub queue: Queue,
+    pub rd_system: ReactionDiffusionSystem,
+    // number of compute steps since the start
+    pub step_count: u64,
+    // clamp dt to the stability limit instead of only warning (see stability.rs)
+    auto_dt: bool,
+    // the last stability warning, printed once and not again for every frame it stays true
+    stability_warning: Option<String>,
+    // divergence flags on their way back from the GPU and the step they were copied at
+    divergence_readback: Option<(readback::PendingReadback, u64)>,
+}
+
+impl Simulation {
+    // target_format is the format of the views passed to render
+    // fails for a grid size the device can not hold or a shader that does not compile
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        target_format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        let rd_system = ReactionDiffusionSystem::new(device, queue, target_format, width, height)?;
+
+        Ok(Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            rd_system,
+            step_count: 0,
+            auto_dt: false,
+            stability_warning: None,
+            divergence_readback: None,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.rd_system.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.rd_system.height
+    }
+
+    pub fn params(&self) -> &SimParams {
+        self.rd_system.params()
+    }
+
+    // a dt above the stability limit is clamped or warned about
+    pub fn set_params(&mut self, params: &SimParams) {
+        self.rd_system.set_params(params);
+        self.check_stability();
+    }
+
+    // feed, kill, du and dv per pixel instead of the global params
+    pub fn set_param_map(&mut self, map: &ParamMap) -> Result<(), String> {
+        self.rd_system.set_param_map(&self.queue, map)?;
+        // the map can have faster diffusion than the params
+        self.check_stability();
+        Ok(())
+    }
+
+    // feed from bottom to top and kill from left to right of the window
+    pub fn set_param_gradient(&mut self, feed: (f32, f32), kill: (f32, f32)) {
+        self.rd_system.set_param_gradient(&self.queue, feed, kill);
+        self.check_stability();
+    }
+
+    // red channel of the PNG is mapped to the feed range, green to the kill range
+    pub fn load_param_map(
+        &mut self,
+        path: &Path,
+        feed: (f32, f32),
+        kill: (f32, f32),
+    ) -> Result<(), String> {
+        self.rd_system
+            .load_param_map(&self.queue, path, feed, kill)?;
+        self.check_stability();
+        Ok(())
+    }
+
+    // back to the global params
+    pub fn clear_param_map(&mut self) {
+        self.rd_system.clear_param_map();
+        self.check_stability();
+    }
+
+    pub fn uses_param_map(&self) -> bool {
+        self.rd_system.use_param_map
+    }
+
+    // None without a parameter map
+    pub fn read_param_map(&self) -> Result<Option<ParamMap>, String> {
+        if !self.uses_param_map() {
+            return Ok(None);
+        }
+        let bytes =
+            readback::read_texture(&self.device, &self.queue, &self.rd_system.param_texture)?;
+        Ok(Some(ParamMap {
+            width: self.width(),
+            height: self.height(),
+            data: bytemuck::cast_slice(&bytes).to_vec(),
+        }))
+    }
+
+    pub fn encode_step(&mut self, encoder: &mut CommandEncoder) {
+        self.rd_system.compute_pass(&self.queue, encoder);
+        self.step_count += 1;
+    }
+
+    // `steps` compute dispatches alternating between the ping-pong textures
+    pub fn encode_steps(&mut self, encoder: &mut CommandEncoder, steps: u32) {
+        for _ in 0..steps {
+            self.encode_step(encoder);
+        }
+    }
+
+    pub fn encode_render(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        self.rd_system.render_pass(encoder, view);
+    }
+
+    // draws the field into a render pass started by the caller (see rd_system::begin_render_pass)
+    pub fn draw(&self, rpass: &mut RenderPass) {
+        self.rd_system.draw(rpass);
+    }
+
+    // [x, y] threads per compute workgroup, rebuilds the compute pipeline
+    pub fn set_workgroup_size(&mut self, size: [u32; 2]) -> Result<(), String> {
+        self.rd_system.set_workgroup_size(&self.device, size)
+    }
+
+    pub fn stencil(&self) -> Stencil {
+        self.rd_system.stencil
+    }
+
+    // the laplacian is baked into the compute pipeline so it is rebuilt
+    pub fn set_stencil(&mut self, stencil: Stencil) -> Result<(), String> {
+        self.rd_system.set_stencil(&self.device, stencil)?;
+        self.check_stability();
+        Ok(())
+    }
+
+    pub fn integrator(&self) -> Integrator {
+        self.rd_system.integrator
+    }
+
+    // every stage of a step is its own compute pipeline, so they are rebuilt
+    pub fn set_integrator(&mut self, integrator: Integrator) -> Result<(), String> {
+        self.rd_system.set_integrator(&self.device, integrator)?;
+        self.check_stability();
+        Ok(())
+    }
+
+    // largest dt the integrator and stencil are stable with, None without a limit (IMEX)
+    pub fn max_stable_dt(&self) -> Option<f32> {
+        stability::max_stable_dt(
+            self.rd_system.max_diffusion(),
+            self.stencil(),
+            self.integrator(),
+        )
+    }
+
+    pub fn auto_dt(&self) -> bool {
+        self.auto_dt
+    }
+
+    // from now on dt is clamped to the stability limit, the current one as well
+    pub fn set_auto_dt(&mut self, auto_dt: bool) {
+        self.auto_dt = auto_dt;
+        self.check_stability();
+    }
+
+    // what the last check said, None while dt is within the limit
+    pub fn stability_warning(&self) -> Option<&str> {
+        self.stability_warning.as_deref()
+    }
+
+    // runs after everything the limit depends on: params, stencil, integrator, param map
+    fn check_stability(&mut self) {
+        let dt = self.params().dt;
+        let warning = match self.max_stable_dt() {
+            Some(max_dt) if dt > max_dt => {
+                let limit = format!(
+                    "the stable limit {:.3} for {} with the {} stencil",
+                    max_dt,
+                    self.integrator(),
+                    self.stencil().name()
+                );
+                if self.auto_dt {
+                    let params = SimParams {
+                        dt: max_dt,
+                        ..*self.params()
+                    };
+                    self.rd_system.set_params(&params);
+                    Some(format!("dt {} clamped to {}", dt, limit))
+                } else {
+                    Some(format!("dt {} is above {} and will blow up", dt, limit))
+                }
+            }
+            _ => None,
+        };
+
+        if warning != self.stability_warning {
+            if let Some(warning) = &warning {
+                eprintln!("Warning: {}", warning);
+            }
+            self.stability_warning = warning;
+        }
+    }
+
+    pub fn detect_divergence(&self) -> bool {
+        self.rd_system.detect_divergence
+    }
+
+    // the check is part of the compute pipelines so they are rebuilt
+    pub fn set_detect_divergence(&mut self, detect: bool) -> Result<(), String> {
+        self.rd_system
+            .set_detect_divergence(&self.device, &self.queue, detect)?;
+        self.divergence_readback = None;
+        Ok(())
+    }
+
+    // waits for the GPU and reads the divergence flags, Some once pixels stayed outside [0, 1]
+    // the flags are cleared after a find so the next call only reports new ones
+    pub fn check_divergence(&mut self) -> Result<Option<Divergence>, String> {
+        if !self.detect_divergence() {
+            return Ok(None);
+        }
+        let bytes =
+            readback::read_buffer(&self.device, &self.queue, &self.rd_system.divergence_buffer)?;
+        Ok(self.divergence_from(&bytes, self.step_count))
+    }
+
+    // like check_divergence without waiting: copies the flags now and poll_divergence reads
+    // them on a later frame. does nothing while the last copy is still on its way
+    pub fn start_divergence_check(&mut self) {
+        if !self.detect_divergence() || self.divergence_readback.is_some() {
+            return;
+        }
+        let readback = readback::PendingReadback::start(
+            &self.device,
+            &self.queue,
+            &self.rd_system.divergence_buffer,
+        );
+        self.divergence_readback = Some((readback, self.step_count));
+    }
+
+    // the result of start_divergence_check once the GPU is done, Ok(None) until then
+    pub fn poll_divergence(&mut self) -> Result<Option<Divergence>, String> {
+        let Some((readback, step)) = &self.divergence_readback else {
+            return Ok(None);
+        };
+        let Some(bytes) = readback.try_read(&self.device) else {
+            return Ok(None);
+        };
+        let step = *step;
+        self.divergence_readback = None;
+        Ok(self.divergence_from(&bytes?, step))
+    }
+
+    // Some if the flags (copied at step) count any pixels
+    fn divergence_from(&mut self, bytes: &[u8], step: u64) -> Option<Divergence> {
+        let flags: DivergenceFlags = bytemuck::pod_read_unaligned(bytes);
+        if flags.pixels == 0 {
+            return None;
+        }
+        self.rd_system.clear_divergence(&self.queue);
+
+        Some(Divergence {
+            step,
+            pixels: flags.pixels,
+            x: flags.first % self.width(),
+            y: flags.first / self.width(),
+            dt: self.params().dt,
+            max_dt: self.max_stable_dt(),
+        })
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.rd_system.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.rd_system.set_palette(&self.queue, palette);
+    }
+
+    pub fn boundary(&self) -> Boundary {
+        self.rd_system.boundary
+    }
+
+    // takes effect with the next step
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.rd_system.boundary = boundary;
+    }
+
+    // walls from a generated or loaded mask, replaces the painted ones
+    pub fn set_mask(&mut self, mask: &Mask) -> Result<(), String> {
+        self.rd_system.set_mask(&self.queue, mask)
+    }
+
+    pub fn clear_mask(&mut self) {
+        self.rd_system.clear_mask(&self.queue);
+    }
+
+    // the walls as they are now, painted ones included
+    pub fn read_mask(&self) -> Result<Mask, String> {
+        let bytes =
+            readback::read_texture(&self.device, &self.queue, &self.rd_system.mask_texture)?;
+        Ok(Mask {
+            width: self.width(),
+            height: self.height(),
+            data: bytemuck::cast_slice(&bytes).to_vec(),
+        })
+    }
+
+    // runs `steps` compute steps in one submission
+    pub fn step(&mut self, steps: u32) {
+        let mut encoder = self.create_encoder("Step Encoder");
+        self.encode_steps(&mut encoder, steps);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    // paints one stroke into the current field right away
+    pub fn paint(&mut self, stroke: &BrushStroke) {
+        let mut encoder = self.create_encoder("Brush Encoder");
+        self.rd_system.brush_pass(&self.queue, &mut encoder, stroke);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    pub fn render(&self, view: &TextureView) {
+        let mut encoder = self.create_encoder("Render Encoder");
+        self.encode_render(&mut encoder, view);
+        self.queue.submit([encoder.finish()]);
+    }
+
+    // current field as RGBA f32 (U, V, 0, 1), rows from top to bottom of the texture
+    pub fn read_field(&self) -> Result<Vec<f32>, String> {
+        let bytes =
+            readback::read_texture(&self.device, &self.queue, self.rd_system.current_texture())?;
+        Ok(bytemuck::cast_slice(&bytes).to_vec())
+    }
+
+    // same layout as read_field, replaces the current state
+    pub fn write_field(&mut self, data: &[f32]) -> Result<(), String> {
+        let expected = (self.width() * self.height() * 4) as usize;
+        if data.len() != expected {
+            return Err(format!(
+                "Field has {} values but the grid needs {}",
+                data.len(),
+                expected
+            ));
+        }
+        self.rd_system.upload_field(&self.queue, data);
+        Ok(())
+    }
+
+    // starts over from a generated state, the same seed gives the same state
+    pub fn reset(&mut self, init: &InitialCondition, seed: u64) -> Result<(), String> {
+        let field = init.generate(self.width(), self.height(), seed)?;
+        self.rd_system.upload_field(&self.queue, &field);
+        // a blow-up of the old field is no longer there, a copy on its way has the old flags
+        self.rd_system.clear_divergence(&self.queue);
+        self.divergence_readback = None;
+        self.step_count = 0;
+        Ok(())
+    }
+
+    // renders the current field into an offscreen texture of any size and reads it back
+    pub fn capture_frame(&self, width: u32, height: u32) -> Result<RgbaImage, String> {
+        let target =
+            OffscreenTarget::new(&self.device, width, height, self.rd_system.target_format);
+        self.render(&target.view);
+        target.read(&self.device, &self.queue)
+    }
+
+    // parameters and step count, stored as text chunks in exported PNGs
+    pub fn png_text(&self) -> PngText {
+        let params = self.params();
+        vec![
+            (
+                "Software".to_string(),
+                "reaction_diffusion_wgpu".to_string(),
+            ),
+            ("du".to_string(), params.du.to_string()),
+            ("dv".to_string(), params.dv.to_string()),
+            ("feed".to_string(), params.feed.to_string()),
+            ("kill".to_string(), params.kill.to_string()),
+            ("dt".to_string(), params.dt.to_string()),
+            ("boundary".to_string(), self.boundary().to_string()),
+            ("stencil".to_string(), self.stencil().name().to_string()),
+            ("integrator".to_string(), self.integrator().to_string()),
+            ("step".to_string(), self.step_count.to_string()),
+        ]
+    }
+
+    // rendered frame as 8 bit PNG
+    pub fn save_screenshot(&self, path: &Path, width: u32, height: u32) -> Result<(), String> {
+        let frame = self.capture_frame(width, height)?;
+        image_io::save_png(path, &frame, &self.png_text())
+    }
+
+    // raw U/V field as 16 bit PNG
+    pub fn save_field(&self, path: &Path) -> Result<(), String> {
+        let field = self.read_field()?;
+        image_io::save_field_png(path, self.width(), self.height(), &field, &self.png_text())
+    }
+
+    // grid, field, ping-pong side, step count, params, boundary, stencil, integrator,
+    // the parameter map and the walls
+    pub fn save_checkpoint(&self, path: &Path) -> Result<(), String> {
+        let param_map = if self.rd_system.use_param_map {
+            let bytes =
+                readback::read_texture(&self.device, &self.queue, &self.rd_system.param_texture)?;
+            Some(bytemuck::cast_slice(&bytes).to_vec())
+        } else {
+            None
+        };
+        let mask = Some(self.read_mask()?).filter(|mask| !mask.is_empty());
+
+        Checkpoint {
+            width: self.width(),
+            height: self.height(),
+            use_1_as_source: self.rd_system.use_1_as_source,
+            step_count: self.step_count,
+            params: *self.params(),
+            boundary: self.boundary(),
+            stencil: self.stencil(),
+            integrator: self.integrator(),
+            field: self.read_field()?,
+            param_map,
+            mask: mask.map(|mask| mask.data),
+        }
+        .save(path)
+    }
+
+    // the textures and bind groups are replaced, palette and workgroup size are not part of
+    // the checkpoint and stay, on an error (file, grid size, shader) nothing changes
+    pub fn load_checkpoint(&mut self, path: &Path) -> Result<(), String> {
+        let checkpoint = Checkpoint::load(path, self.device.limits().max_texture_dimension_2d)?;
+
+        let mut rd_system = self.build_system(
+            checkpoint.width,
+            checkpoint.height,
+            checkpoint.stencil,
+            checkpoint.integrator,
+        )?;
+        rd_system.set_params(&checkpoint.params);
+        rd_system.boundary = checkpoint.boundary;
+        // Checkpoint::load checked the lengths against the grid size
+        rd_system.upload_field(&self.queue, &checkpoint.field);
+        rd_system.use_1_as_source = checkpoint.use_1_as_source;
+        if let Some(data) = checkpoint.param_map {
+            rd_system.set_param_map(
+                &self.queue,
+                &ParamMap {
+                    width: checkpoint.width,
+                    height: checkpoint.height,
+                    data,
+                },
+            )?;
+        }
+        if let Some(data) = checkpoint.mask {
+            rd_system.set_mask(
+                &self.queue,
+                &Mask {
+                    width: checkpoint.width,
+                    height: checkpoint.height,
+                    data,
+                },
+            )?;
+        }
+
+        // nothing can fail from here on
+        self.rd_system = rd_system;
+        self.divergence_readback = None;
+        self.step_count = checkpoint.step_count;
+        // params, stencil, integrator and the param map are all there now
+        self.check_stability();
+        Ok(())
+    }
+
+    // new textures and bind groups for another grid size, the field has to be reset afterwards
+    // params, palette, boundary, stencil, integrator, workgroup size and the divergence check stay
+    // the param map and the walls are dropped, on an error (grid size, shader) nothing changes
+    pub fn resize_grid(&mut self, width: u32, height: u32) -> Result<(), String> {
+        if width == self.width() && height == self.height() {
+            return Ok(());
+        }
+        self.rd_system = self.build_system(width, height, self.stencil(), self.integrator())?;
+        self.divergence_readback = None;
+        self.check_stability();
+        Ok(())
+    }
+
+    // a new system with the settings of the running one, built on the side so the running
+    // one is only replaced once every step that can fail went through
+    fn build_system(
+        &self,
+        width: u32,
+        height: u32,
+        stencil: Stencil,
+        integrator: Integrator,
+    ) -> Result<ReactionDiffusionSystem, String> {
+        let mut rd_system = ReactionDiffusionSystem::new(
+            &self.device,
+            &self.queue,
+            self.rd_system.target_format,
+            width,
+            height,
+        )?;
+        rd_system.set_params(self.params());
+        rd_system.set_palette(&self.queue, self.palette());
+        rd_system.boundary = self.boundary();
+        rd_system.set_stencil(&self.device, stencil)?;
+        rd_system.set_integrator(&self.device, integrator)?;
+        rd_system.set_detect_divergence(&self.device, &self.queue, self.detect_divergence())?;
+        rd_system.set_workgroup_size(&self.device, self.rd_system.workgroup_size)?;
+        Ok(rd_system)
+    }
+
+    pub fn rebuild(&mut self, kind: PipelineKind) -> Result<(), ShaderError> {
+        self.rd_system.rebuild(&self.device, kind)
+    }
+
+    pub fn rebuild_pipeline(&mut self) -> Result<(), Vec<ShaderError>> {
+        self.rd_system.rebuild_pipeline(&self.device)
+    }
+
+    fn create_encoder(&self, label: &str) -> CommandEncoder {
+        self.device
+            .create_command_encoder(&CommandEncoderDescriptor { label: Some(label) })
+    }
+}
@@ -0,0 +1,87 @@
+// how large dt can get before a step blows up
+//
+// the explicit integrators stay stable while dt * D * max_eigenvalue is inside their stability
+// interval. D is the fastest diffusion rate and max_eigenvalue how fast the quickest mode of
+// the laplacian decays (8 for the 5-point stencil, so dt * D * 4 < 1 for Euler). the reaction
+// terms are left out of the estimate, SAFETY_FACTOR makes room for them.
+// IMEX treats the diffusion implicitly, but its Jacobi iterations only converge while
+// dt * D is below the stencil's jacobi_limit. that is no limit at all for the stencils
+// without negative weights, fourth-order needs dt * D < 1.5
+//
+// the clamp to [0, 1] in the compute shader turns a blow-up into noise that looks like a
+// pattern, the divergence check looks at the values before the clamp and stops instead
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::rd_system::{Integrator, Stencil};
+
+// the limit is this fraction of the interval, the reaction shifts the eigenvalues a bit
+// off the real axis and right at the limit the checkerboard never decays
+pub const SAFETY_FACTOR: f32 = 0.9;
+
+// runs with the divergence check read the flags after this many steps. headless waits for
+// the GPU, the window copies them and reads them on a later frame (poll_divergence)
+pub const DIVERGENCE_CHECK_EVERY: u32 = 100;
+
+// the [stability] section of scene files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StabilitySettings {
+    // clamp dt to the limit instead of only warning
+    pub auto_dt: bool,
+    // look for NaN and values outside [0, 1] and pause when the field diverges
+    pub detect_divergence: bool,
+}
+
+// largest stable dt, None without a limit (IMEX with most stencils or no diffusion at all)
+pub fn max_stable_dt(max_diffusion: f32, stencil: Stencil, integrator: Integrator) -> Option<f32> {
+    // the largest stable dt * D
+    let limit = match integrator {
+        Integrator::Imex { .. } => stencil.jacobi_limit()?,
+        _ => integrator.stability_interval()? / stencil.max_eigenvalue(),
+    };
+    (max_diffusion > 0.0).then(|| SAFETY_FACTOR * limit / max_diffusion)
+}
+
+// what the divergence check found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    // found after this step
+    pub step: u64,
+    // updates outside [0, 1] since the last check, a pixel counts once per step
+    // once it stayed outside for a few steps in a row
+    pub pixels: u32,
+    // the first one in row order (texture coordinates, row 0 is at the bottom)
+    pub x: u32,
+    pub y: u32,
+    pub dt: f32,
+    pub max_dt: Option<f32>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Field diverged by step {}: {} pixel updates stayed NaN or outside [0, 1], the first at ({}, {})",
+            self.step, self.pixels, self.x, self.y
+        )?;
+        match self.max_dt {
+            Some(max_dt) if self.dt > max_dt => write!(
+                f,
+                ". dt {} is above the stable limit {:.3}",
+                self.dt, max_dt
+            ),
+            Some(max_dt) => write!(
+                f,
+                ". dt {} is below the diffusion limit {:.3}, the reaction may need a smaller one",
+                self.dt, max_dt
+            ),
+            None => write!(
+                f,
+                ". dt {} has no diffusion limit here, the reaction may need a smaller one",
+                self.dt
+            ),
+        }
+    }
+}
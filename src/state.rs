@@ -1,29 +1,172 @@
 use crate::{
+    cli::Options,
     gpu_resources::{FrameContext, GpuResource},
-    rd_system::ReactionDiffusionSystem,
+    ui::{Panel, Ui},
+};
+use reaction_diffusion_wgpu::{
+    Simulation,
+    brush::{self, BrushMode, BrushSettings, BrushStroke},
+    clock::SimClock,
+    initial_conditions::InitialCondition,
+    mask::MaskShape,
+    param_map::ParamMapSource,
+    presets::PRESETS,
+    rd_system::{self, PipelineKind},
+    recorder::{Recorder, RecorderConfig},
+    scene::{PresentMode, Scene},
+    shader_check::ShaderError,
     shader_watcher::ShaderWatcher,
+    stability::DIVERGENCE_CHECK_EVERY,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use wgpu::SurfaceError;
-use winit::{dpi::PhysicalSize, window::Window};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, MouseButton, WindowEvent},
+    keyboard::{Key, NamedKey},
+    window::Window,
+};
+
+// where P and F write their PNGs
+const SCREENSHOT_DIR: &str = "screenshots";
+// every R recording gets its own directory in here
+const RECORDING_DIR: &str = "recordings";
+// F5 saves and F9 loads this checkpoint
+const QUICKSAVE_PATH: &str = "checkpoints/quicksave.rdck";
+// S saves here when the app was not started with --scene
+const SCENE_DIR: &str = "scenes";
+// a watched file is reloaded once it had no events for this long
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
 
 pub struct State {
+    window: &'static Window,
     gpu_res: GpuResource,
-    rd_system: ReactionDiffusionSystem,
+    simulation: Simulation,
     shader_watcher: ShaderWatcher,
+    shaders_dir: PathBuf,
+    // the --scene file as the watcher reports it, edits to it are applied live
+    watched_scene: Option<PathBuf>,
+    // pipelines whose last reload failed, they keep running their last good shader
+    shader_errors: BTreeMap<PipelineKind, ShaderError>,
+    clock: SimClock,
+    ui: Ui,
+    // what Backspace, N and I reset to
+    initial_condition: InitialCondition,
+    seed: u64,
+    // what the walls were made from (--mask or the scene), painted walls come on top
+    mask: Option<MaskShape>,
+    // what the parameter map was made from (--param-map or the scene)
+    param_map: Option<ParamMapSource>,
+    // index into PRESETS of the last chosen preset
+    preset: Option<usize>,
+    present_mode: PresentMode,
+    // --scene file, S writes the live state back into it
+    scene_path: Option<PathBuf>,
+    // Some while recording
+    recorder: Option<Recorder>,
+    record_every: u32,
+    // left drag paints with paint_mode, right drag erases (walls in wall mode)
+    brush: BrushSettings,
+    paint_mode: BrushMode,
+    // last cursor position in grid coordinates
+    cursor: Option<[f32; 2]>,
+    // Some while a mouse button is held
+    stroke_mode: Option<BrushMode>,
+    // diagnostic of the last divergence, until the next reset
+    divergence: Option<String>,
+    // step count of the last divergence check
+    divergence_checked_at: u64,
 }
 
 impl State {
-    pub async fn new(window: &'static Window) -> Result<Self, String> {
-        let gpu_res = GpuResource::new(window).await?;
-        let rd_system = ReactionDiffusionSystem::new(&gpu_res);
-        let shaders_path = format!("{}/shaders", env!("CARGO_MANIFEST_DIR")); // absolute address 
-        println!("Watching Shaders at: {}", shaders_path);
-        let shader_watcher = ShaderWatcher::new(shaders_path);
+    pub async fn new(window: &'static Window, options: &Options) -> Result<Self, String> {
+        let mut gpu_res = GpuResource::new(window).await?;
+        gpu_res.set_present_mode(options.present_mode.into())?;
+        let mut simulation = Simulation::new(
+            &gpu_res.device,
+            &gpu_res.queue,
+            gpu_res.surface_format(),
+            options.width,
+            options.height,
+        )?;
+        simulation.reset(&options.init, options.seed)?;
+        // stencil and integrator before the params, the stable dt depends on them
+        simulation.set_stencil(options.stencil)?;
+        simulation.set_integrator(options.integrator)?;
+        simulation.set_auto_dt(options.stability.auto_dt);
+        simulation.set_params(&options.params);
+        simulation.set_detect_divergence(options.stability.detect_divergence)?;
+        simulation.set_palette(options.palette);
+        simulation.set_boundary(options.boundary);
+        if let Some(param_map) = &options.param_map {
+            simulation.set_param_map(&param_map.generate(options.width, options.height)?)?;
+        }
+        if let Some(mask) = &options.mask {
+            simulation.set_mask(&mask.generate(options.width, options.height)?)?;
+        }
+        simulation.set_workgroup_size(options.workgroup_size)?;
+        if let Some(path) = &options.checkpoint {
+            simulation.load_checkpoint(path)?;
+            println!("Resuming {:?} at step {}", path, simulation.step_count);
+        }
+        let shaders_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("shaders"); // absolute address
+        // the same form as the include paths of the preprocessor, so events can be matched to them
+        let shaders_path = fs::canonicalize(&shaders_path).unwrap_or(shaders_path);
+        println!("Watching Shaders at: {:?}", shaders_path);
+        let mut shader_watcher = ShaderWatcher::new(&shaders_path);
+        let watched_scene =
+            options
+                .scene
+                .as_ref()
+                .and_then(|path| match shader_watcher.watch_file(path) {
+                    Ok(path) => {
+                        println!("Watching Scene at: {:?}", path);
+                        Some(path)
+                    }
+                    Err(e) => {
+                        eprintln!("Scene edits will not be applied: {}", e);
+                        None
+                    }
+                });
+
+        let mut clock = SimClock::new(options.steps_per_frame);
+        clock.paused = options.paused;
+        let ui = Ui::new(window, &gpu_res.device, gpu_res.surface_format());
 
         Ok(Self {
+            window,
             gpu_res,
-            rd_system,
+            simulation,
             shader_watcher,
+            shaders_dir: shaders_path,
+            watched_scene,
+            shader_errors: BTreeMap::new(),
+            clock,
+            ui,
+            initial_condition: options.init.clone(),
+            seed: options.seed,
+            mask: options.mask.clone(),
+            // a checkpoint brings its own map, there is no source for it
+            param_map: match options.checkpoint {
+                Some(_) => None,
+                None => options.param_map.clone(),
+            },
+            preset: options.preset,
+            present_mode: options.present_mode,
+            scene_path: options.scene.clone(),
+            recorder: None,
+            record_every: options.record_every,
+            brush: options.brush,
+            paint_mode: BrushMode::PaintV,
+            cursor: None,
+            stroke_mode: None,
+            divergence: None,
+            divergence_checked_at: 0,
         })
     }
 
@@ -31,17 +174,616 @@ impl State {
         self.gpu_res.resize(new_size);
     }
 
+    // P = screenshot of the window, F = raw U/V field, R = start/stop recording
+    // F5 = save checkpoint, F9 = load checkpoint
+    // Backspace = reset, N = reset with a new seed, I = next initial condition
+    // B = brush paints V, U or walls, [ and ] = smaller/bigger brush
+    // Space = pause/resume, . = single step, + and - = more/less steps per frame
+    // Page Up/Down = previous/next preset, Tab = show/hide the panel, S = save the scene
+    pub fn key_pressed(&mut self, key: &Key) {
+        let c = match key {
+            Key::Character(c) => c,
+            Key::Named(NamedKey::Tab) => return self.ui.visible = !self.ui.visible,
+            Key::Named(NamedKey::PageDown) => return self.cycle_preset(1),
+            Key::Named(NamedKey::PageUp) => return self.cycle_preset(PRESETS.len() - 1),
+            Key::Named(NamedKey::Space) => {
+                self.clock.toggle_pause();
+                let state = if self.clock.paused {
+                    "Paused"
+                } else {
+                    "Running"
+                };
+                return println!("{} at step {}", state, self.simulation.step_count);
+            }
+            Key::Named(NamedKey::Backspace) => return self.reset(),
+            Key::Named(NamedKey::F5) => return report_saved(self.save_quicksave()),
+            Key::Named(NamedKey::F9) => return self.load_quicksave(),
+            _ => return,
+        };
+
+        match c.to_lowercase().as_str() {
+            "p" => report_saved(self.save_screenshot()),
+            "f" => report_saved(self.save_field()),
+            "s" => report_saved(self.save_scene()),
+            "r" => self.toggle_recording(),
+            "n" => {
+                self.seed = self.seed.wrapping_add(1);
+                self.reset();
+            }
+            "i" => {
+                let names = InitialCondition::NAMES;
+                let current = names
+                    .iter()
+                    .position(|&n| n == self.initial_condition.name())
+                    .unwrap_or(0);
+                let next = names[(current + 1) % names.len()];
+                self.initial_condition =
+                    InitialCondition::from_name(next).expect("NAMES are all valid");
+                self.reset();
+            }
+            "b" => {
+                self.paint_mode = match self.paint_mode {
+                    BrushMode::PaintV => BrushMode::PaintU,
+                    BrushMode::PaintU => BrushMode::Wall,
+                    _ => BrushMode::PaintV,
+                };
+                println!("Brush paints {:?}", self.paint_mode);
+            }
+            "." => self.clock.single_step(),
+            "+" | "=" => {
+                self.clock.faster();
+                println!("{} steps per frame", self.clock.steps_per_frame);
+            }
+            "-" => {
+                self.clock.slower();
+                println!("{} steps per frame", self.clock.steps_per_frame);
+            }
+            "[" => self.resize_brush(1.0 / 1.25),
+            "]" => self.resize_brush(1.25),
+            _ => {}
+        }
+    }
+
+    // true if the panel used the event and the simulation should ignore it
+    pub fn ui_event(&mut self, event: &WindowEvent) -> bool {
+        self.ui.on_window_event(self.window, event)
+    }
+
+    fn panel(&self) -> Panel {
+        Panel {
+            params: *self.simulation.params(),
+            palette: self.simulation.palette(),
+            boundary: self.simulation.boundary(),
+            stencil: self.simulation.stencil(),
+            integrator: self.simulation.integrator(),
+            auto_dt: self.simulation.auto_dt(),
+            detect_divergence: self.simulation.detect_divergence(),
+            paused: self.clock.paused,
+            steps_per_frame: self.clock.steps_per_frame,
+            brush: self.brush,
+            paint_mode: self.paint_mode,
+            step_count: self.simulation.step_count,
+            max_dt: self.simulation.max_stable_dt(),
+            divergence: self.divergence.clone(),
+            preset: self.preset,
+            reset: false,
+            clear_walls: false,
+            single_step: false,
+            screenshot: false,
+        }
+    }
+
+    fn apply_panel(&mut self, panel: Panel) {
+        if let Some(index) = panel.preset
+            && panel.preset != self.preset
+        {
+            // a new preset also replaces the params from the sliders
+            return self.apply_preset(index);
+        }
+        if panel.params != *self.simulation.params() {
+            self.simulation.set_params(&panel.params);
+        }
+        if panel.palette != self.simulation.palette() {
+            self.simulation.set_palette(panel.palette);
+        }
+        self.simulation.set_boundary(panel.boundary);
+        if panel.stencil != self.simulation.stencil()
+            && let Err(e) = self.simulation.set_stencil(panel.stencil)
+        {
+            eprintln!("{}", e);
+        }
+        if panel.integrator != self.simulation.integrator()
+            && let Err(e) = self.simulation.set_integrator(panel.integrator)
+        {
+            eprintln!("{}", e);
+        }
+        if panel.auto_dt != self.simulation.auto_dt() {
+            self.simulation.set_auto_dt(panel.auto_dt);
+        }
+        if panel.detect_divergence != self.simulation.detect_divergence() {
+            self.set_detect_divergence(panel.detect_divergence);
+        }
+        if panel.paused != self.clock.paused {
+            self.clock.toggle_pause();
+        }
+        self.clock.steps_per_frame = panel.steps_per_frame;
+        self.brush = panel.brush;
+        self.paint_mode = panel.paint_mode;
+
+        if panel.single_step {
+            self.clock.single_step();
+        }
+        if panel.reset {
+            self.reset();
+        }
+        if panel.clear_walls {
+            self.set_mask(None);
+        }
+        if panel.screenshot {
+            report_saved(self.save_screenshot());
+        }
+    }
+
+    fn resize_brush(&mut self, factor: f32) {
+        self.brush.radius = (self.brush.radius * factor).clamp(1.0, 500.0);
+        println!("Brush radius {:.1}", self.brush.radius);
+    }
+
+    pub fn mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        let mode = match button {
+            MouseButton::Left => self.paint_mode,
+            MouseButton::Right if self.paint_mode == BrushMode::Wall => BrushMode::ClearWall,
+            MouseButton::Right => BrushMode::Erase,
+            _ => return,
+        };
+
+        match state {
+            ElementState::Pressed => {
+                self.stroke_mode = Some(mode);
+                // a click without moving still paints a dot
+                if let Some(position) = self.cursor {
+                    self.paint(position, position);
+                }
+            }
+            ElementState::Released if self.stroke_mode == Some(mode) => self.stroke_mode = None,
+            ElementState::Released => {}
+        }
+    }
+
+    pub fn cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        let size = self.gpu_res.size;
+        let grid = brush::window_to_grid(
+            (position.x, position.y),
+            (size.width, size.height),
+            (self.simulation.width(), self.simulation.height()),
+        );
+
+        // connect to the last position so fast drags do not leave gaps
+        let last = self.cursor.replace(grid);
+        if let Some(last) = last {
+            self.paint(last, grid);
+        }
+    }
+
+    pub fn cursor_left(&mut self) {
+        self.cursor = None;
+    }
+
+    fn paint(&mut self, from: [f32; 2], to: [f32; 2]) {
+        let Some(mode) = self.stroke_mode else {
+            return;
+        };
+        self.simulation.paint(&BrushStroke {
+            from,
+            to,
+            mode,
+            settings: self.brush,
+        });
+    }
+
+    // moves `offset` presets forward (wrapping), the first press starts at alpha
+    fn cycle_preset(&mut self, offset: usize) {
+        let next = match self.preset {
+            Some(index) => (index + offset) % PRESETS.len(),
+            None => 0,
+        };
+        self.apply_preset(next);
+    }
+
+    // parameters and the recommended initial condition of the preset
+    fn apply_preset(&mut self, index: usize) {
+        let preset = &PRESETS[index];
+        self.preset = Some(index);
+        self.simulation.set_params(&preset.params);
+        self.initial_condition = preset.initial_condition();
+        println!("Preset '{}': {}", preset.name, preset.description);
+        self.reset();
+    }
+
+    fn set_detect_divergence(&mut self, detect: bool) {
+        match self.simulation.set_detect_divergence(detect) {
+            Ok(()) => self.divergence_checked_at = self.simulation.step_count,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    // reads the flags copied a few frames ago without waiting for the GPU
+    // a blow-up pauses the simulation so the field can be looked at
+    fn poll_divergence(&mut self) {
+        match self.simulation.poll_divergence() {
+            Ok(Some(divergence)) => {
+                eprintln!("{}", divergence);
+                if !self.clock.paused {
+                    self.clock.toggle_pause();
+                }
+                self.divergence = Some(divergence.to_string());
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Divergence check failed: {}", e),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.divergence = None;
+        match self.simulation.reset(&self.initial_condition, self.seed) {
+            Ok(()) => println!(
+                "Reset to '{}' with seed {}",
+                self.initial_condition.name(),
+                self.seed
+            ),
+            Err(e) => eprintln!("Reset failed: {}", e),
+        }
+    }
+
+    // walls from the shape, None removes all of them (painted ones too)
+    fn set_mask(&mut self, mask: Option<MaskShape>) {
+        let result = match &mask {
+            Some(shape) => shape
+                .generate(self.simulation.width(), self.simulation.height())
+                .and_then(|mask| self.simulation.set_mask(&mask)),
+            None => {
+                self.simulation.clear_mask();
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => self.mask = mask,
+            Err(e) => eprintln!("Mask not applied: {}", e),
+        }
+    }
+
+    // per pixel feed and kill from the source, None goes back to the global params
+    fn set_param_map(&mut self, param_map: Option<ParamMapSource>) {
+        let result = match &param_map {
+            Some(source) => source
+                .generate(self.simulation.width(), self.simulation.height())
+                .and_then(|map| self.simulation.set_param_map(&map)),
+            None => {
+                self.simulation.clear_param_map();
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => self.param_map = param_map,
+            Err(e) => eprintln!("Parameter map not applied: {}", e),
+        }
+    }
+
+    // everything needed to start this setup again with --scene
+    fn scene(&self) -> Scene {
+        let mut scene =
+            Scene::from_simulation(&self.simulation, &self.initial_condition, self.seed);
+        scene.display.present_mode = self.present_mode;
+        scene.display.steps_per_frame = self.clock.steps_per_frame;
+        scene.brush = self.brush;
+        scene.mask = self.mask.clone();
+        scene.param_map = self.param_map.clone();
+        scene
+    }
+
+    // applies an edited --scene file to the running simulation
+    // params, parameter map, palette, speed, brush, walls and present mode change without touching the field,
+    // only a new grid size rebuilds the textures and resets
+    fn reload_scene(&mut self, path: &Path) {
+        let scene = match Scene::load(path) {
+            Ok(scene) => scene,
+            Err(e) => return eprintln!("Scene not reloaded: {}", e),
+        };
+        // S writing the file also ends up here
+        if scene == self.scene() {
+            return;
+        }
+        println!("Scene has been changed: {:?}", path);
+
+        // stencil and integrator before the params, the stable dt depends on them
+        if scene.grid.stencil != self.simulation.stencil()
+            && let Err(e) = self.simulation.set_stencil(scene.grid.stencil)
+        {
+            eprintln!("{}", e);
+        }
+        if scene.integrator != self.simulation.integrator()
+            && let Err(e) = self.simulation.set_integrator(scene.integrator)
+        {
+            eprintln!("{}", e);
+        }
+        self.simulation.set_auto_dt(scene.stability.auto_dt);
+        if scene.params != *self.simulation.params() {
+            self.simulation.set_params(&scene.params);
+            self.preset = None;
+        }
+        if scene.display.palette != self.simulation.palette() {
+            self.simulation.set_palette(scene.display.palette);
+        }
+        self.simulation.set_boundary(scene.boundary);
+        if scene.stability.detect_divergence != self.simulation.detect_divergence() {
+            self.set_detect_divergence(scene.stability.detect_divergence);
+        }
+        self.clock.steps_per_frame = scene.display.steps_per_frame;
+        self.brush = scene.brush;
+        if scene.display.present_mode != self.present_mode {
+            match self
+                .gpu_res
+                .set_present_mode(scene.display.present_mode.into())
+            {
+                Ok(()) => self.present_mode = scene.display.present_mode,
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        if scene.grid.workgroup_size != self.simulation.rd_system.workgroup_size
+            && let Err(e) = self
+                .simulation
+                .set_workgroup_size(scene.grid.workgroup_size)
+        {
+            eprintln!("{}", e);
+        }
+
+        if scene.mask != self.mask {
+            self.set_mask(scene.mask.clone());
+        }
+        if scene.param_map != self.param_map {
+            self.set_param_map(scene.param_map.clone());
+        }
+
+        let init_changed = scene.init != self.initial_condition || scene.seed != self.seed;
+        self.initial_condition = scene.init;
+        self.seed = scene.seed;
+
+        let grid = scene.grid;
+        if grid.width != self.simulation.width() || grid.height != self.simulation.height() {
+            if let Err(e) = self.simulation.resize_grid(grid.width, grid.height) {
+                return self.show_load_error(format!("Resizing the grid failed: {}", e));
+            }
+            self.ui.load_error = None;
+            // the last cursor position was in the old grid
+            self.cursor = None;
+            println!("Grid is now {}x{}", grid.width, grid.height);
+            // the new textures have no walls and no parameter map
+            self.set_mask(scene.mask);
+            self.set_param_map(scene.param_map);
+            self.reset();
+        } else if init_changed {
+            println!(
+                "'{}' with seed {} is used from the next reset (Backspace)",
+                self.initial_condition.name(),
+                self.seed
+            );
+        }
+    }
+
+    fn save_scene(&self) -> Result<PathBuf, String> {
+        let path = match &self.scene_path {
+            Some(path) => path.clone(),
+            None => {
+                fs::create_dir_all(SCENE_DIR)
+                    .map_err(|e| format!("Failed to create {:?}: {}", SCENE_DIR, e))?;
+                PathBuf::from(SCENE_DIR).join(format!("scene_{}.toml", self.simulation.step_count))
+            }
+        };
+        self.scene().save(&path)?;
+        Ok(path)
+    }
+
+    fn save_quicksave(&self) -> Result<PathBuf, String> {
+        let path = PathBuf::from(QUICKSAVE_PATH);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        }
+        self.simulation.save_checkpoint(&path)?;
+        Ok(path)
+    }
+
+    fn load_quicksave(&mut self) {
+        match self
+            .simulation
+            .load_checkpoint(&PathBuf::from(QUICKSAVE_PATH))
+        {
+            Ok(()) => {
+                self.ui.load_error = None;
+                // the checkpoint replaced the map (or cleared it), there is no source for it.
+                // a scene reload with a [param_map] sets that one again
+                self.param_map = None;
+                println!(
+                    "Loaded {:?} at step {}",
+                    QUICKSAVE_PATH, self.simulation.step_count
+                )
+            }
+            Err(e) => self.show_load_error(format!("Loading failed: {}", e)),
+        }
+    }
+
+    // a failed scene resize or F9 keeps the old simulation running, the window says why
+    fn show_load_error(&mut self, message: String) {
+        eprintln!("{}", message);
+        self.ui.load_error = Some(message);
+    }
+
+    fn toggle_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(frames) => println!("Recording stopped after {} frames", frames),
+                Err(e) => eprintln!("Recording failed: {}", e),
+            }
+            return;
+        }
+
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let dir = PathBuf::from(RECORDING_DIR).join(format!("run_{}", seconds));
+        match Recorder::new(RecorderConfig::in_dir(&dir, self.record_every)) {
+            Ok(recorder) => {
+                println!("Recording every {}. frame to {:?}", self.record_every, dir);
+                self.recorder = Some(recorder);
+            }
+            Err(e) => eprintln!("Recording failed: {}", e),
+        }
+    }
+
+    // called after every presented frame
+    fn record_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if !recorder.wants_frame() {
+            return;
+        }
+
+        let size = self.gpu_res.size;
+        let result = self
+            .simulation
+            .capture_frame(size.width.max(1), size.height.max(1))
+            .and_then(|frame| recorder.add_frame(&frame, &self.simulation.png_text()));
+        if let Err(e) = result {
+            eprintln!("Recording failed, stopping: {}", e);
+            // the frames so far are still a valid GIF
+            self.toggle_recording();
+        }
+    }
+
+    fn save_screenshot(&self) -> Result<PathBuf, String> {
+        let path = screenshot_path(&format!("frame_{}.png", self.simulation.step_count))?;
+        let size = self.gpu_res.size;
+        self.simulation
+            .save_screenshot(&path, size.width.max(1), size.height.max(1))?;
+        Ok(path)
+    }
+
+    fn save_field(&self) -> Result<PathBuf, String> {
+        let path = screenshot_path(&format!("field_{}.png", self.simulation.step_count))?;
+        self.simulation.save_field(&path)?;
+        Ok(path)
+    }
+
+    // only .wgsl files in the shader directory that a pipeline is built from or #includes
+    // swap files, backups and other files the editor writes next to them are ignored
+    fn pipelines_for(&self, path: &Path) -> Vec<PipelineKind> {
+        if !path.starts_with(&self.shaders_dir)
+            || path.extension().and_then(|e| e.to_str()) != Some("wgsl")
+        {
+            return Vec::new();
+        }
+        self.simulation.rd_system.pipelines_using(path)
+    }
+
+    fn rebuild(&mut self, kind: PipelineKind) {
+        match self.simulation.rebuild(kind) {
+            Ok(()) => {
+                self.shader_errors.remove(&kind);
+                // most likely the shader that made the load fail
+                self.ui.load_error = None;
+            }
+            Err(e) => {
+                eprintln!("Shader error in {}", e);
+                self.shader_errors.insert(kind, e);
+            }
+        }
+
+        // the overlay stays until every pipeline compiles again
+        self.ui.shader_error = if self.shader_errors.is_empty() {
+            None
+        } else {
+            // a broken #include fails every pipeline that uses it, show it once
+            let mut text: Vec<String> = Vec::new();
+            for e in self.shader_errors.values().map(|e| e.to_string()) {
+                if !text.contains(&e) {
+                    text.push(e);
+                }
+            }
+            Some(text.join("\n\n"))
+        };
+    }
+
     pub fn render(&mut self) -> Result<(), SurfaceError> {
-        // is anything changed?
-        while let Ok(path) = self.shader_watcher.reciever_x.try_recv() {
-            println!("Shader has been changed: {:?}", path);
-            self.rd_system.rebuild_pipeline(&self.gpu_res);
+        // is anything changed? (once the editor is done writing)
+        for path in self.shader_watcher.settled_changes(WATCH_DEBOUNCE) {
+            if Some(&path) == self.watched_scene.as_ref() {
+                self.reload_scene(&path);
+            } else {
+                let kinds = self.pipelines_for(&path);
+                if !kinds.is_empty() {
+                    println!("Shader has been changed: {:?}", path);
+                }
+                for kind in kinds {
+                    self.rebuild(kind);
+                }
+            }
         }
 
         let mut frame: FrameContext = self.gpu_res.begin_frame()?;
-        self.rd_system
-            .compute_and_render_pass(&self.gpu_res, &mut frame);
+
+        // the panel changes params, clock and brush before this frame's steps
+        let mut panel = self.panel();
+        self.ui.run(
+            self.window,
+            &self.gpu_res.device,
+            &self.gpu_res.queue,
+            &mut frame.encoder,
+            &mut panel,
+        );
+        self.apply_panel(panel);
+
+        let steps = self.clock.steps_for_frame();
+        self.simulation.encode_steps(&mut frame.encoder, steps);
+        {
+            // field first, panel on top of it in the same pass
+            let mut rpass =
+                rd_system::begin_render_pass(&mut frame.encoder, &frame.view).forget_lifetime();
+            self.simulation.draw(&mut rpass);
+            self.ui.paint(&mut rpass);
+        }
         self.gpu_res.submit_frame(frame);
+
+        // a paused simulation would only record the same frame again
+        if steps > 0 {
+            self.record_frame();
+        }
+        // the flags are copied after this frame's steps and read on a later frame once the GPU
+        // got there, so the frame loop never waits for them
+        self.poll_divergence();
+        // a reset or a checkpoint moves the step count backwards too
+        if self
+            .simulation
+            .step_count
+            .abs_diff(self.divergence_checked_at)
+            >= DIVERGENCE_CHECK_EVERY as u64
+        {
+            self.divergence_checked_at = self.simulation.step_count;
+            self.simulation.start_divergence_check();
+        }
         Ok(())
     }
 }
+
+fn report_saved(result: Result<PathBuf, String>) {
+    match result {
+        Ok(path) => println!("Saved {:?}", path),
+        Err(e) => eprintln!("Saving failed: {}", e),
+    }
+}
+
+fn screenshot_path(file_name: &str) -> Result<PathBuf, String> {
+    fs::create_dir_all(SCREENSHOT_DIR)
+        .map_err(|e| format!("Failed to create {:?}: {}", SCREENSHOT_DIR, e))?;
+    Ok(PathBuf::from(SCREENSHOT_DIR).join(file_name))
+}
@@ -0,0 +1,311 @@
+use egui::{ClippedPrimitive, Context, TextureId, ViewportId};
+use egui_wgpu::{Renderer, ScreenDescriptor};
+use reaction_diffusion_wgpu::{
+    Boundary, Integrator, Palette, SimParams, Stencil,
+    brush::{BrushMode, BrushSettings},
+    clock::MAX_STEPS_PER_FRAME,
+    presets::PRESETS,
+};
+use wgpu::{CommandEncoder, Device, Queue, RenderPass, TextureFormat};
+use winit::{event::WindowEvent, window::Window};
+
+// everything the panel shows, State fills it before and applies it after every frame
+pub struct Panel {
+    pub params: SimParams,
+    pub palette: Palette,
+    pub boundary: Boundary,
+    pub stencil: Stencil,
+    pub integrator: Integrator,
+    pub auto_dt: bool,
+    pub detect_divergence: bool,
+    pub paused: bool,
+    pub steps_per_frame: u32,
+    pub brush: BrushSettings,
+    pub paint_mode: BrushMode,
+    pub step_count: u64,
+    // largest stable dt for the current settings, None for IMEX
+    pub max_dt: Option<f32>,
+    // what the divergence check found, shown until the next reset
+    pub divergence: Option<String>,
+    // index into PRESETS
+    pub preset: Option<usize>,
+
+    // buttons clicked this frame
+    pub reset: bool,
+    pub clear_walls: bool,
+    pub single_step: bool,
+    pub screenshot: bool,
+}
+
+// egui overlay, drawn into the same render pass after the field
+pub struct Ui {
+    pub visible: bool,
+    // diagnostic of the last failed shader reload, shown over everything until it compiles
+    pub shader_error: Option<String>,
+    // why the last scene resize or checkpoint load failed, until the next one works
+    pub load_error: Option<String>,
+    egui_state: egui_winit::State,
+    renderer: Renderer,
+    // output of the last run, drawn by paint
+    paint_jobs: Vec<ClippedPrimitive>,
+    screen: ScreenDescriptor,
+    // freed at the start of the next run when nothing uses them anymore
+    textures_to_free: Vec<TextureId>,
+}
+
+impl Ui {
+    pub fn new(window: &Window, device: &Device, target_format: TextureFormat) -> Self {
+        let egui_state = egui_winit::State::new(
+            Context::default(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            window.theme(),
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let renderer = Renderer::new(device, target_format, None, 1, false);
+
+        Self {
+            visible: true,
+            shader_error: None,
+            load_error: None,
+            egui_state,
+            renderer,
+            paint_jobs: Vec::new(),
+            screen: ScreenDescriptor {
+                size_in_pixels: [1, 1],
+                pixels_per_point: 1.0,
+            },
+            textures_to_free: Vec::new(),
+        }
+    }
+
+    // true if egui used the event (typing into a field, clicking on the panel)
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.egui_state.on_window_event(window, event).consumed
+    }
+
+    // builds the panel for this frame and uploads what egui needs to draw it
+    pub fn run(
+        &mut self,
+        window: &Window,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        panel: &mut Panel,
+    ) {
+        for id in self.textures_to_free.drain(..) {
+            self.renderer.free_texture(&id);
+        }
+
+        let input = self.egui_state.take_egui_input(window);
+        let ctx = self.egui_state.egui_ctx().clone();
+        let visible = self.visible;
+        let errors: Vec<(&str, &str)> = [
+            self.shader_error
+                .as_deref()
+                .map(|text| ("Shader error, still running the last good version", text)),
+            self.load_error
+                .as_deref()
+                .map(|text| ("Not loaded, still running the last setup", text)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let output = ctx.run(input, |ctx| {
+            if visible {
+                panel_window(ctx, panel);
+            }
+            if !errors.is_empty() {
+                error_overlay(ctx, &errors);
+            }
+        });
+        self.egui_state
+            .handle_platform_output(window, output.platform_output);
+
+        let size = window.inner_size();
+        self.screen = ScreenDescriptor {
+            size_in_pixels: [size.width.max(1), size.height.max(1)],
+            pixels_per_point: output.pixels_per_point,
+        };
+        self.paint_jobs = ctx.tessellate(output.shapes, output.pixels_per_point);
+
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.textures_to_free = output.textures_delta.free;
+
+        // egui only returns extra command buffers for paint callbacks, which we don't use
+        let _ =
+            self.renderer
+                .update_buffers(device, queue, encoder, &self.paint_jobs, &self.screen);
+    }
+
+    pub fn paint(&self, rpass: &mut RenderPass<'static>) {
+        self.renderer.render(rpass, &self.paint_jobs, &self.screen);
+    }
+}
+
+// translucent box along the top of the window, the simulation stays visible below it
+// one title and text per error
+fn error_overlay(ctx: &Context, errors: &[(&str, &str)]) {
+    egui::Area::new(egui::Id::new("shader_error"))
+        .order(egui::Order::Foreground)
+        .anchor(egui::Align2::LEFT_TOP, [8.0, 8.0])
+        .interactable(false)
+        .show(ctx, |ui| {
+            egui::Frame::new()
+                .fill(egui::Color32::from_black_alpha(200))
+                .stroke(egui::Stroke::new(1.0, egui::Color32::from_rgb(220, 60, 60)))
+                .inner_margin(10.0)
+                .corner_radius(4.0)
+                .show(ui, |ui| {
+                    ui.set_max_width(ctx.screen_rect().width() - 36.0);
+                    for (title, text) in errors {
+                        ui.label(
+                            egui::RichText::new(*title)
+                                .color(egui::Color32::from_rgb(255, 110, 110))
+                                .strong(),
+                        );
+                        ui.label(
+                            egui::RichText::new(*text)
+                                .monospace()
+                                .color(egui::Color32::from_gray(230)),
+                        );
+                    }
+                });
+        });
+}
+
+fn panel_window(ctx: &Context, panel: &mut Panel) {
+    egui::Window::new("Reaction Diffusion")
+        .default_width(260.0)
+        .show(ctx, |ui| {
+            ui.label(format!("Step {}  (Tab hides this panel)", panel.step_count));
+            if let Some(text) = &panel.divergence {
+                ui.label(egui::RichText::new(text).color(egui::Color32::from_rgb(255, 110, 110)));
+            }
+
+            ui.horizontal(|ui| {
+                let pause_text = if panel.paused { "Resume" } else { "Pause" };
+                if ui.button(pause_text).clicked() {
+                    panel.paused = !panel.paused;
+                }
+                panel.single_step = ui.button("Step").clicked();
+                panel.reset = ui.button("Reset").clicked();
+                panel.screenshot = ui.button("Screenshot").clicked();
+            });
+            ui.add(
+                egui::Slider::new(&mut panel.steps_per_frame, 1..=MAX_STEPS_PER_FRAME)
+                    .logarithmic(true)
+                    .text("steps per frame"),
+            );
+
+            ui.separator();
+            ui.heading("Parameters");
+            let selected = panel.preset.map_or("custom", |i| PRESETS[i].name);
+            egui::ComboBox::from_label("preset")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (index, preset) in PRESETS.iter().enumerate() {
+                        ui.selectable_value(&mut panel.preset, Some(index), preset.name)
+                            .on_hover_text(preset.description);
+                    }
+                });
+            let params = &mut panel.params;
+            ui.add(egui::Slider::new(&mut params.du, 0.0..=1.0).text("DU"));
+            ui.add(egui::Slider::new(&mut params.dv, 0.0..=1.0).text("DV"));
+            ui.add(
+                egui::Slider::new(&mut params.feed, 0.0..=0.1)
+                    .max_decimals(4)
+                    .text("feed"),
+            );
+            ui.add(
+                egui::Slider::new(&mut params.kill, 0.0..=0.1)
+                    .max_decimals(4)
+                    .text("kill"),
+            );
+            ui.add(egui::Slider::new(&mut params.dt, 0.01..=2.0).text("dt"));
+            match panel.max_dt {
+                Some(max_dt) if params.dt > max_dt => ui.label(
+                    egui::RichText::new(format!("unstable above dt {:.3}", max_dt))
+                        .color(egui::Color32::from_rgb(255, 110, 110)),
+                ),
+                Some(max_dt) => ui.label(format!("stable up to dt {:.3}", max_dt)),
+                None => ui.label("no dt limit from the diffusion"),
+            };
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut panel.auto_dt, "clamp dt")
+                    .on_hover_text("keep dt below the stable limit");
+                ui.checkbox(&mut panel.detect_divergence, "pause on blow-up")
+                    .on_hover_text("pause when the field turns NaN or leaves [0, 1]");
+            });
+
+            // by name, so picking dirichlet again keeps its values
+            egui::ComboBox::from_label("boundary")
+                .selected_text(panel.boundary.name())
+                .show_ui(ui, |ui| {
+                    for name in Boundary::NAMES {
+                        let selected = panel.boundary.name() == name;
+                        if ui.selectable_label(selected, name).clicked() && !selected {
+                            panel.boundary = Boundary::parse(name).expect("NAMES are all valid");
+                        }
+                    }
+                });
+            if let Boundary::Dirichlet { u, v } = &mut panel.boundary {
+                ui.add(egui::Slider::new(u, 0.0..=1.0).text("U outside"));
+                ui.add(egui::Slider::new(v, 0.0..=1.0).text("V outside"));
+            }
+            egui::ComboBox::from_label("laplacian")
+                .selected_text(panel.stencil.name())
+                .show_ui(ui, |ui| {
+                    for stencil in Stencil::ALL {
+                        ui.selectable_value(&mut panel.stencil, stencil, stencil.name());
+                    }
+                });
+            // by name too, so imex keeps its iterations
+            egui::ComboBox::from_label("integrator")
+                .selected_text(panel.integrator.name())
+                .show_ui(ui, |ui| {
+                    for name in Integrator::NAMES {
+                        let selected = panel.integrator.name() == name;
+                        if ui.selectable_label(selected, name).clicked() && !selected {
+                            panel.integrator =
+                                Integrator::parse(name).expect("NAMES are all valid");
+                        }
+                    }
+                });
+            if let Integrator::Imex { iterations } = &mut panel.integrator {
+                ui.add(egui::Slider::new(iterations, 1..=100).text("Jacobi iterations"));
+            }
+
+            ui.separator();
+            ui.heading("Brush");
+            ui.horizontal(|ui| {
+                ui.label("left button paints");
+                ui.radio_value(&mut panel.paint_mode, BrushMode::PaintV, "V");
+                ui.radio_value(&mut panel.paint_mode, BrushMode::PaintU, "U");
+                ui.radio_value(&mut panel.paint_mode, BrushMode::Wall, "walls");
+            });
+            if ui.button("clear walls").clicked() {
+                panel.clear_walls = true;
+            }
+            let brush = &mut panel.brush;
+            ui.add(
+                egui::Slider::new(&mut brush.radius, 1.0..=200.0)
+                    .logarithmic(true)
+                    .text("radius"),
+            );
+            ui.add(egui::Slider::new(&mut brush.strength, 0.0..=1.0).text("strength"));
+            ui.add(egui::Slider::new(&mut brush.falloff, 0.0..=1.0).text("falloff"));
+
+            ui.separator();
+            egui::ComboBox::from_label("palette")
+                .selected_text(panel.palette.name())
+                .show_ui(ui, |ui| {
+                    for palette in Palette::ALL {
+                        ui.selectable_value(&mut panel.palette, palette, palette.name());
+                    }
+                });
+        });
+}